@@ -0,0 +1,209 @@
+//! Completing names in the composer, from the people seen on a server.
+
+use crate::net::commands::ChatCommands;
+
+/// Names seen on a server, the most recently active first.
+#[derive(Default, Debug)]
+pub struct Nicks {
+    names: Vec<String>,
+}
+
+impl Nicks {
+    /// Moves `name` to the front, as they have just done something.
+    fn touch(&mut self, name: &str) {
+        self.names.retain(|n| n != name);
+        self.names.insert(0, name.to_string());
+    }
+
+    /// Adds `name` behind everyone active, if they haven't been seen yet.
+    fn seen(&mut self, name: &str) {
+        if !self.names.iter().any(|n| n == name) {
+            self.names.push(name.to_string());
+        }
+    }
+
+    pub fn apply(&mut self, command: &ChatCommands) {
+        match command {
+            ChatCommands::Message { sender, .. }
+            | ChatCommands::PrivateMessage { sender, .. }
+            | ChatCommands::RoomMessage { sender, .. } => self.touch(sender),
+
+            ChatCommands::UserConnected { name } | ChatCommands::UserJoined { name, .. } => {
+                self.touch(name)
+            }
+
+            ChatCommands::UserRenamed { oldname, newname } => {
+                match self.names.iter_mut().find(|n| *n == oldname) {
+                    Some(name) => *name = newname.clone(),
+                    None => self.touch(newname),
+                }
+            }
+
+            ChatCommands::UserList { names } | ChatCommands::RoomUsers { names, .. } => {
+                for name in names {
+                    self.seen(name);
+                }
+            }
+
+            _ => (),
+        }
+    }
+
+    /// Names starting with `prefix`, ignoring case, the most recently active
+    /// first.
+    pub fn matching(&self, prefix: &str) -> Vec<String> {
+        let prefix = prefix.to_lowercase();
+
+        self.names
+            .iter()
+            .filter(|name| name.to_lowercase().starts_with(&prefix))
+            .cloned()
+            .collect()
+    }
+}
+
+/// A name being completed, cycled through each time Tab is pressed.
+#[derive(Debug)]
+pub struct Completion {
+    /// The text before the name.
+    before: String,
+    /// The text after the cursor when completion started.
+    after: String,
+    candidates: Vec<String>,
+    selected: Option<usize>,
+    /// The text as last completed, to tell when it's been edited since.
+    text: String,
+}
+
+impl Completion {
+    /// Starts completing the word just before `cursor`, counted in
+    /// characters, with anyone in `nicks` but `own` name.
+    pub fn start(text: &str, cursor: usize, nicks: &Nicks, own: &str) -> Option<Self> {
+        let cursor = text
+            .char_indices()
+            .nth(cursor)
+            .map_or(text.len(), |(i, _)| i);
+        let (before, after) = text.split_at(cursor);
+
+        // Names can be addressed with an @ in front
+        let start = before
+            .rfind(|c: char| c.is_whitespace() || c == '@')
+            .map_or(0, |i| i + before[i..].chars().next().unwrap().len_utf8());
+        let (before, word) = before.split_at(start);
+        if word.is_empty() {
+            return None;
+        }
+
+        let candidates = nicks
+            .matching(word)
+            .into_iter()
+            .filter(|name| name != own)
+            .collect::<Vec<_>>();
+        if candidates.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            before: before.to_string(),
+            after: after.to_string(),
+            candidates,
+            selected: None,
+            text: text.to_string(),
+        })
+    }
+
+    /// Whether `text` is still as this completion left it.
+    pub fn is_current(&self, text: &str) -> bool {
+        self.text == text
+    }
+
+    pub fn candidates(&self) -> &[String] {
+        &self.candidates
+    }
+
+    pub fn selected(&self) -> Option<usize> {
+        self.selected
+    }
+
+    /// Puts the next candidate in `text`, returning where the cursor goes
+    /// after it.
+    pub fn next(&mut self, text: &mut String) -> usize {
+        let i = self.selected.map_or(0, |i| (i + 1) % self.candidates.len());
+        self.select(i, text)
+    }
+
+    /// Puts candidate `i` in `text`, returning where the cursor goes after
+    /// it.
+    pub fn select(&mut self, i: usize, text: &mut String) -> usize {
+        // Someone addressed at the start of a line gets a colon
+        let suffix = if self.before.is_empty() { ": " } else { " " };
+        let completed = format!("{}{}{}", self.before, self.candidates[i], suffix);
+
+        // Don't double up the space the rest already starts with
+        let after = self.after.strip_prefix(' ').unwrap_or(&self.after);
+
+        *text = format!("{}{}", completed, after);
+        self.text = text.clone();
+        self.selected = Some(i);
+
+        completed.chars().count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nicks() -> Nicks {
+        let mut nicks = Nicks::default();
+        for command in [
+            "u alice bob barbara",
+            "m bob hi",
+            "c bernard",
+            "m alice hello",
+            "r bernard bert",
+        ] {
+            nicks.apply(&command.parse().unwrap());
+        }
+        nicks
+    }
+
+    #[test]
+    fn names_are_ordered_by_activity() {
+        let nicks = nicks();
+
+        assert_eq!(nicks.matching(""), ["alice", "bert", "bob", "barbara"]);
+        assert_eq!(nicks.matching("B"), ["bert", "bob", "barbara"]);
+    }
+
+    #[test]
+    fn tab_cycles_through_names() {
+        let nicks = nicks();
+        let mut text = "b".to_string();
+
+        let mut completion = Completion::start(&text, 1, &nicks, "bob").unwrap();
+        assert_eq!(completion.candidates(), ["bert", "barbara"]);
+        assert_eq!(completion.next(&mut text), 6);
+        assert_eq!(text, "bert: ");
+        assert!(completion.is_current(&text));
+
+        completion.next(&mut text);
+        assert_eq!(text, "barbara: ");
+        completion.next(&mut text);
+        assert_eq!(text, "bert: ");
+    }
+
+    #[test]
+    fn names_are_completed_mid_line() {
+        let nicks = nicks();
+
+        let mut text = "ask @al about it".to_string();
+        let mut completion = Completion::start(&text, 7, &nicks, "bob").unwrap();
+        assert_eq!(completion.next(&mut text), 11);
+        assert_eq!(text, "ask @alice about it");
+
+        let text = "nobody here".to_string();
+        assert!(Completion::start(&text, 6, &nicks, "bob").is_none());
+        assert!(Completion::start("hi ", 3, &nicks, "bob").is_none());
+    }
+}
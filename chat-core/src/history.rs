@@ -0,0 +1,500 @@
+use crate::{
+    net::commands::{self, Record},
+    storage,
+};
+
+use std::{
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufRead, BufReader, Seek, SeekFrom, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// How much chat history is kept for a server.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Retention {
+    pub days: Option<u32>,
+    pub messages: Option<usize>,
+    /// Most messages of each chat held in memory, the older ones being read
+    /// back from disk when scrolled to.
+    pub in_memory: usize,
+}
+
+impl Default for Retention {
+    fn default() -> Self {
+        Self {
+            days: Some(30),
+            messages: None,
+            in_memory: 5_000,
+        }
+    }
+}
+
+/// Stored as the receive time in milliseconds, then the line as it came from
+/// the server.
+fn to_line(record: &Record) -> String {
+    format!("{} {}\n", commands::to_millis(record.time), record)
+}
+
+fn from_line(line: &str) -> Option<Record> {
+    let (millis, record) = line.split_once(' ')?;
+
+    Some(Record {
+        time: commands::from_millis(millis)?,
+        ..record.parse().ok()?
+    })
+}
+
+/// A name for `key`'s files that is safe on any filesystem and can't be
+/// shared with another key, by percent-encoding all but letters, digits and
+/// `-._`.
+pub(crate) fn file_name(key: &str) -> String {
+    key.bytes()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || b"-._".contains(&c) {
+                (c as char).to_string()
+            } else {
+                format!("%{:02X}", c)
+            }
+        })
+        .collect()
+}
+
+/// How files used to be named, which several keys could share.
+fn legacy_file_name(key: &str) -> String {
+    key.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Where `key`'s file with `extension` is kept in `dir`, taking over one
+/// named the old way if there's nothing there yet.
+pub(crate) fn file_path(dir: &Path, key: &str, extension: &str) -> PathBuf {
+    let path = dir.join(format!("{}.{}", file_name(key), extension));
+    let legacy = dir.join(format!("{}.{}", legacy_file_name(key), extension));
+
+    // Whichever key sharing the old name is opened first gets the file
+    if legacy != path && !path.exists() && legacy.exists() {
+        if let Err(e) = fs::rename(&legacy, &path) {
+            tracing::warn!("Couldn't rename {}: {}", legacy.display(), e);
+        }
+    }
+
+    path
+}
+
+/// Where histories are kept, next to eframe's own storage.
+pub fn dir() -> Option<PathBuf> {
+    storage::dir().map(|dir| dir.join("history"))
+}
+
+/// Log of one chat, appended to as messages arrive.
+///
+/// Rows are the readable entries counted from the start of the file, so only
+/// one `History` can have a log open at a time, across every client running.
+/// Lines that can't be read, such as those from a newer client, are kept but
+/// not counted.
+pub struct History {
+    path: PathBuf,
+    file: Option<File>,
+    /// Where each row starts in the file, then where the file ends, so rows
+    /// can be read without going through everything before them. Only known
+    /// once the file has been loaded.
+    offsets: Option<Vec<u64>>,
+    /// Locked for as long as the log is open.
+    _lock: File,
+}
+
+impl History {
+    /// Opens the history for `server` in the default directory.
+    pub fn open(server: &str) -> io::Result<Self> {
+        let dir = dir().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No home directory for history")
+        })?;
+
+        Self::open_in(&dir, server)
+    }
+
+    /// Opens the history for `server` in `dir`, failing if it is already
+    /// open, whether in this client or another.
+    pub fn open_in(dir: &Path, server: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = file_path(dir, server, "log");
+
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path.with_extension("lock"))?;
+        match lock.try_lock() {
+            Ok(()) => (),
+            Err(TryLockError::WouldBlock) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::ResourceBusy,
+                    format!("{} is already open elsewhere", path.display()),
+                ))
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+
+        Ok(Self {
+            path,
+            file: None,
+            offsets: None,
+            _lock: lock,
+        })
+    }
+
+    /// Reads back the stored history, dropping anything `retention` no
+    /// longer allows from the file.
+    pub fn load(&mut self, retention: &Retention) -> io::Result<Vec<Record>> {
+        let file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                self.offsets = Some(vec![0]);
+                return Ok(Vec::new());
+            }
+            Err(e) => return Err(e),
+        };
+
+        let mut reader = BufReader::new(file);
+        let mut lines = Vec::new();
+        let mut offsets = Vec::new();
+        let mut end = 0;
+        loop {
+            let mut line = String::new();
+            let read = reader.read_line(&mut line)?;
+            if read == 0 {
+                break;
+            }
+
+            // Anything unreadable is kept as it is but not counted, rather
+            // than losing it or the whole history
+            let entry = from_line(line.trim_end_matches(['\n', '\r']));
+            if entry.is_some() {
+                offsets.push(end);
+            }
+            end += read as u64;
+            lines.push((line, entry));
+        }
+        offsets.push(end);
+        self.offsets = Some(offsets);
+
+        // Which lines are kept, unreadable ones always
+        let mut kept = vec![true; lines.len()];
+
+        if let Some(days) = retention.days {
+            let oldest = SystemTime::now() - Duration::from_secs(u64::from(days) * 24 * 60 * 60);
+            for (kept, (_, entry)) in kept.iter_mut().zip(&lines) {
+                if entry.as_ref().is_some_and(|entry| entry.time < oldest) {
+                    *kept = false;
+                }
+            }
+        }
+
+        if let Some(messages) = retention.messages {
+            let readable = (0..lines.len())
+                .filter(|&i| kept[i] && lines[i].1.is_some())
+                .collect::<Vec<_>>();
+            for &i in &readable[..readable.len().saturating_sub(messages)] {
+                kept[i] = false;
+            }
+        }
+
+        let lines = lines
+            .into_iter()
+            .zip(&kept)
+            .filter(|(_, kept)| **kept)
+            .map(|(line, _)| line)
+            .collect::<Vec<_>>();
+
+        if kept.contains(&false) {
+            self.replace(
+                lines
+                    .iter()
+                    .map(|(line, entry)| (line.as_str(), entry.is_some())),
+            )?;
+        }
+
+        Ok(lines.into_iter().filter_map(|(_, entry)| entry).collect())
+    }
+
+    /// Reads back the stored entries in `range`, counted from the oldest, as
+    /// left by the last `load`.
+    pub fn read(&self, range: Range<usize>) -> io::Result<Vec<Record>> {
+        let mut file = match File::open(&self.path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        // Rows before the range are only read through if it isn't known where
+        // the range starts
+        let skip = match &self.offsets {
+            Some(offsets) => {
+                let start = offsets[range.start.min(offsets.len() - 1)];
+                file.seek(SeekFrom::Start(start))?;
+                0
+            }
+            None => range.start,
+        };
+
+        // Unreadable lines aren't rows
+        let readable = BufReader::new(file)
+            .lines()
+            .filter_map(|line| line.map(|line| from_line(&line)).transpose());
+
+        let mut entries = Vec::with_capacity(range.len());
+        for entry in readable.skip(skip).take(range.len()) {
+            entries.push(entry?);
+        }
+
+        Ok(entries)
+    }
+
+    pub fn append(&mut self, record: &Record) -> io::Result<()> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => self.file.insert(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            ),
+        };
+
+        let line = to_line(record);
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            // Part of the line may have made it
+            self.offsets = None;
+            return Err(e);
+        }
+
+        if let Some(offsets) = &mut self.offsets {
+            offsets.push(offsets[offsets.len() - 1] + line.len() as u64);
+        }
+        Ok(())
+    }
+
+    /// Deletes the log, for a chat that has been merged into another.
+    pub fn remove(self) -> io::Result<()> {
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Replaces everything in the log with `entries`.
+    pub fn rewrite(&mut self, entries: &[Record]) -> io::Result<()> {
+        let lines = entries.iter().map(to_line).collect::<Vec<_>>();
+
+        self.replace(lines.iter().map(|line| (line.as_str(), true)))
+    }
+
+    /// Replaces everything in the log with `lines`, each with its newline
+    /// and whether it's readable.
+    fn replace<'a>(&mut self, lines: impl Iterator<Item = (&'a str, bool)>) -> io::Result<()> {
+        self.file = None;
+        self.offsets = None;
+
+        let temp = self.path.with_extension("log.tmp");
+        let mut file = File::create(&temp)?;
+        let mut offsets = Vec::new();
+        let mut end = 0;
+        for (line, readable) in lines {
+            if readable {
+                offsets.push(end);
+            }
+            file.write_all(line.as_bytes())?;
+            end += line.len() as u64;
+        }
+        offsets.push(end);
+        fs::rename(temp, &self.path)?;
+
+        self.offsets = Some(offsets);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::net::commands::ChatCommands;
+
+    fn message(text: &str) -> ChatCommands {
+        ChatCommands::Message {
+            sender: "alice".to_string(),
+            message: text.to_string(),
+        }
+    }
+
+    fn days_ago(days: u64) -> SystemTime {
+        SystemTime::now() - Duration::from_secs(days * 24 * 60 * 60)
+    }
+
+    #[test]
+    fn entries_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let entries = vec![
+            Record::now(message("hello")),
+            Record {
+                server_time: Some(days_ago(1)),
+                ..Record::now(ChatCommands::UserConnected {
+                    name: "bob".to_string(),
+                })
+            },
+        ];
+
+        let mut history = History::open_in(dir.path(), "example.com:6078").unwrap();
+        for entry in &entries {
+            history.append(entry).unwrap();
+        }
+        drop(history);
+
+        let mut history = History::open_in(dir.path(), "example.com:6078").unwrap();
+        let loaded = history.load(&Retention::default()).unwrap();
+
+        // Only millisecond precision is kept
+        assert_eq!(loaded.len(), 2);
+        for (loaded, entry) in loaded.iter().zip(&entries) {
+            assert_eq!(loaded.command, entry.command);
+            assert!(entry.time.duration_since(loaded.time).unwrap() < Duration::from_millis(1));
+            assert_eq!(loaded.server_time.is_some(), entry.server_time.is_some());
+        }
+
+        let other = History::open_in(dir.path(), "other.com")
+            .unwrap()
+            .load(&Retention::default());
+        assert!(other.unwrap().is_empty());
+    }
+
+    #[test]
+    fn retention_drops_old_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::open_in(dir.path(), "server").unwrap();
+
+        for (days, text) in [(10, "old"), (3, "recent"), (2, "newer"), (1, "newest")] {
+            let entry = Record {
+                time: days_ago(days),
+                ..Record::now(message(text))
+            };
+            history.append(&entry).unwrap();
+        }
+
+        let retention = Retention {
+            days: Some(5),
+            messages: Some(2),
+            ..Retention::default()
+        };
+        let loaded = history.load(&retention).unwrap();
+        assert_eq!(
+            loaded.iter().map(|e| e.command.clone()).collect::<Vec<_>>(),
+            vec![message("newer"), message("newest")]
+        );
+
+        // The dropped entries are gone from disk too
+        let loaded = history
+            .load(&Retention {
+                days: None,
+                messages: None,
+                ..Retention::default()
+            })
+            .unwrap();
+        assert_eq!(loaded.len(), 2);
+
+        history.append(&Record::now(message("later"))).unwrap();
+        assert_eq!(history.load(&Retention::default()).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn unreadable_lines_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let lines = [
+            to_line(&Record::now(message("one"))),
+            "from a newer client\n".to_string(),
+            to_line(&Record::now(message("two"))),
+        ];
+        fs::write(dir.path().join("server.log"), lines.concat()).unwrap();
+
+        let mut history = History::open_in(dir.path(), "server").unwrap();
+        assert_eq!(history.load(&Retention::default()).unwrap().len(), 2);
+        assert_eq!(history.read(1..2).unwrap()[0].command, message("two"));
+
+        // Even when retention drops what is around them
+        let retention = Retention {
+            messages: Some(1),
+            ..Retention::default()
+        };
+        let loaded = history.load(&retention).unwrap();
+        assert_eq!(loaded.len(), 1);
+        assert_eq!(history.read(0..1).unwrap()[0].command, message("two"));
+
+        let stored = fs::read_to_string(dir.path().join("server.log")).unwrap();
+        assert_eq!(stored, lines[1..].concat());
+    }
+
+    #[test]
+    fn histories_have_a_file_each() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut colon = History::open_in(dir.path(), "host:6078").unwrap();
+        colon.append(&Record::now(message("colon"))).unwrap();
+        let mut underscore = History::open_in(dir.path(), "host_6078").unwrap();
+        assert!(underscore.load(&Retention::default()).unwrap().is_empty());
+
+        // Only one writer at a time
+        let error = History::open_in(dir.path(), "host:6078").err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::ResourceBusy);
+        drop(colon);
+        let mut colon = History::open_in(dir.path(), "host:6078").unwrap();
+        assert_eq!(colon.load(&Retention::default()).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn old_file_names_are_taken_over() {
+        let dir = tempfile::tempdir().unwrap();
+        let entry = to_line(&Record::now(message("before")));
+        fs::write(dir.path().join("example_com_6078.log"), entry).unwrap();
+
+        let mut history = History::open_in(dir.path(), "example.com:6078").unwrap();
+        assert_eq!(history.load(&Retention::default()).unwrap().len(), 1);
+        assert!(dir.path().join("example.com%3A6078.log").exists());
+    }
+
+    #[test]
+    fn ranges_are_read_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut history = History::open_in(dir.path(), "server").unwrap();
+        assert!(history.read(0..10).unwrap().is_empty());
+
+        for text in ["one", "two", "three", "four"] {
+            history.append(&Record::now(message(text))).unwrap();
+        }
+
+        let read = history.read(1..3).unwrap();
+        assert_eq!(
+            read.iter().map(|e| e.command.clone()).collect::<Vec<_>>(),
+            vec![message("two"), message("three")]
+        );
+        assert_eq!(history.read(3..10).unwrap().len(), 1);
+
+        // Once loaded, rows are found by where they start in the file
+        drop(history);
+        let mut history = History::open_in(dir.path(), "server").unwrap();
+        assert_eq!(history.load(&Retention::default()).unwrap().len(), 4);
+        history.append(&Record::now(message("five"))).unwrap();
+        let read = history.read(3..5).unwrap();
+        assert_eq!(
+            read.iter().map(|e| e.command.clone()).collect::<Vec<_>>(),
+            vec![message("four"), message("five")]
+        );
+        assert!(history.read(5..10).unwrap().is_empty());
+
+        history.rewrite(&[Record::now(message("only"))]).unwrap();
+        assert_eq!(history.read(0..1).unwrap()[0].command, message("only"));
+        assert!(history.read(1..2).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,20 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! The chat client without a UI: the protocol, keeping connected to servers
+//! and what has been said on them.
+
+/// Name of the app, which is also where eframe keeps its storage.
+pub const APP_NAME: &str = "Chatting client";
+
+pub mod complete;
+pub mod history;
+pub mod mention;
+pub mod net;
+pub mod notify;
+pub mod outbox;
+pub mod roster;
+pub mod search;
+pub mod session;
+pub mod slash;
+pub mod storage;
+pub mod timestamp;
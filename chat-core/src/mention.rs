@@ -0,0 +1,34 @@
+/// Whether `text` mentions `name` as a word of its own, ignoring case.
+pub fn mentions(text: &str, name: &str) -> bool {
+    if name.is_empty() {
+        return false;
+    }
+
+    let text = text.to_lowercase();
+    let name = name.to_lowercase();
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+    text.match_indices(&name).any(|(start, _)| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + name.len()..].chars().next();
+
+        !before.is_some_and(is_word) && !after.is_some_and(is_word)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_must_stand_alone() {
+        assert!(mentions("alice: are you there?", "alice"));
+        assert!(mentions("thanks @Alice!", "alice"));
+        assert!(mentions("ALICE", "alice"));
+        assert!(mentions("malice, then alice", "alice"));
+
+        assert!(!mentions("malice aforethought", "alice"));
+        assert!(!mentions("alice_smith said hi", "alice"));
+        assert!(!mentions("anything", ""));
+    }
+}
@@ -0,0 +1,299 @@
+use crate::net::{
+    commands::*,
+    connection::ConnectionData,
+    error::NetError,
+    transport::{self, Reader, Transport, Writer},
+};
+
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt},
+    runtime,
+    sync::mpsc,
+    task::JoinHandle,
+    time::{self, Instant, MissedTickBehavior},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Lets whoever shows a session know that something changed, such as a UI
+/// that only redraws when it has to.
+#[derive(Clone)]
+pub struct Waker(Arc<dyn Fn() + Send + Sync>);
+
+impl Waker {
+    pub fn new(wake: impl Fn() + Send + Sync + 'static) -> Self {
+        Self(Arc::new(wake))
+    }
+
+    pub fn wake(&self) {
+        (self.0)()
+    }
+}
+
+/// Wakes nobody, for when updates are polled for anyway.
+impl Default for Waker {
+    fn default() -> Self {
+        Self::new(|| ())
+    }
+}
+
+/// How long a stopped network task gets to disconnect cleanly before it is
+/// aborted.
+pub const SHUTDOWN_GRACE: Duration = Duration::from_secs(1);
+
+/// A running `network` task, which disconnects from the server when stopped
+/// or dropped.
+pub struct NetworkTask {
+    cancel: CancellationToken,
+    task: Option<JoinHandle<()>>,
+}
+
+impl NetworkTask {
+    pub fn spawn(
+        send: mpsc::Sender<ClientCommands>,
+        recv: mpsc::Receiver<ClientRequest>,
+        waker: Waker,
+        connection: ConnectionData,
+    ) -> Self {
+        let cancel = CancellationToken::new();
+        let task = tokio::spawn(network(send, recv, waker, connection, cancel.clone()));
+
+        Self {
+            cancel,
+            task: Some(task),
+        }
+    }
+
+    /// Tells the task to disconnect, returning a handle that finishes once it
+    /// has, or once it has been aborted for taking longer than
+    /// `SHUTDOWN_GRACE`. Returns `None` if the task was already stopped.
+    pub fn stop(&mut self) -> Option<JoinHandle<()>> {
+        self.cancel.cancel();
+        let mut task = self.task.take()?;
+
+        Some(tokio::spawn(async move {
+            if time::timeout(SHUTDOWN_GRACE, &mut task).await.is_err() {
+                task.abort();
+            }
+        }))
+    }
+}
+
+impl Drop for NetworkTask {
+    fn drop(&mut self) {
+        // Without a runtime there's nothing to wait for the task on, so it is
+        // cut short instead
+        if runtime::Handle::try_current().is_ok() {
+            self.stop();
+        } else {
+            self.cancel.cancel();
+            if let Some(task) = self.task.take() {
+                task.abort();
+            }
+        }
+    }
+}
+
+/// Connects to the server in `connection`, reconnecting as its policy
+/// allows, until the connection is given up on or `cancel` is triggered.
+///
+/// Cancelling tells the server we are leaving and closes the connection, and
+/// nothing more is reported to the tab.
+pub async fn network(
+    send: mpsc::Sender<ClientCommands>,
+    recv: mpsc::Receiver<ClientRequest>,
+    waker: Waker,
+    connection: ConnectionData,
+    cancel: CancellationToken,
+) {
+    let transport = transport::for_connection(&connection);
+
+    network_with(&*transport, send, recv, waker, connection, cancel).await
+}
+
+/// Runs the client over the given transport rather than the one picked from
+/// `connection`'s server address.
+pub async fn network_with(
+    transport: &dyn Transport,
+    send: mpsc::Sender<ClientCommands>,
+    mut recv: mpsc::Receiver<ClientRequest>,
+    waker: Waker,
+    connection: ConnectionData,
+    cancel: CancellationToken,
+) {
+    let policy = connection.reconnect();
+    let mut attempt = 0;
+
+    loop {
+        let connected = tokio::select! {
+            connected = transport.connect() => connected,
+            _ = cancel.cancelled() => return,
+        };
+
+        let (state, error) = match connected {
+            Ok(stream) => {
+                attempt = 0;
+
+                if send
+                    .send(ClientCommands::ConnectState(ConnectState::Connected))
+                    .await
+                    .is_err()
+                {
+                    return;
+                }
+                waker.wake();
+
+                let result = session(stream, &send, &mut recv, &waker, &connection, &cancel).await;
+                if cancel.is_cancelled() {
+                    return;
+                }
+
+                (ConnectState::Disconnect, result.err())
+            }
+            Err(e) => (ConnectState::Failed, Some(e)),
+        };
+
+        let fatal = error.as_ref().is_some_and(NetError::is_fatal);
+
+        // The tab may already be gone, in which case there is nobody left to tell
+        if let Some(e) = error {
+            if send.send(ClientCommands::Error(e)).await.is_err() {
+                return;
+            }
+        }
+
+        attempt += 1;
+        if send.is_closed() || fatal || !policy.should_retry(attempt) {
+            let _ = send.send(ClientCommands::ConnectState(state)).await;
+            waker.wake();
+            return;
+        }
+
+        // Count down to the next attempt, updating the tab every second
+        let mut next_in = policy.delay(attempt);
+        while !next_in.is_zero() {
+            let state = ConnectState::Reconnecting { attempt, next_in };
+            if send
+                .send(ClientCommands::ConnectState(state))
+                .await
+                .is_err()
+            {
+                return;
+            }
+            waker.wake();
+
+            let step = next_in.min(Duration::from_secs(1));
+            tokio::select! {
+                _ = time::sleep(step) => next_in -= step,
+                _ = cancel.cancelled() => return,
+            }
+        }
+    }
+}
+
+/// Runs a connected session until the server closes the connection, the tab
+/// goes away, the session is cancelled, or something goes wrong.
+async fn session(
+    (reader, mut writer): (Reader, Writer),
+    send: &mpsc::Sender<ClientCommands>,
+    recv: &mut mpsc::Receiver<ClientRequest>,
+    waker: &Waker,
+    connection: &ConnectionData,
+    cancel: &CancellationToken,
+) -> Result<(), NetError> {
+    let mut lines = reader.lines();
+    let keepalive = connection.keepalive().clone();
+
+    // Pings carry the time they were sent, counted from here
+    let start = Instant::now();
+
+    // Handle user input and pings, finishing once the tab drops its sender or
+    // cancels the session
+    let name = connection.name().to_owned();
+    let interval = keepalive.interval;
+    let writing = async move {
+        writer.write_all(name.as_bytes()).await?;
+        writer.write_u8(b'\n').await?;
+
+        let mut pings = time::interval_at(start + interval, interval);
+        pings.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            let request = tokio::select! {
+                // Whatever was queued before the tab went goes out first
+                biased;
+
+                request = recv.recv() => match request {
+                    Some(request) => request,
+                    None => break,
+                },
+
+                _ = pings.tick(), if keepalive.enabled => ClientRequest::Ping {
+                    token: start.elapsed().as_micros().to_string(),
+                },
+
+                _ = cancel.cancelled() => break,
+            };
+            let line = format!("{}\n", request);
+
+            writer.write_all(line.as_bytes()).await?;
+            writer.flush().await?;
+        }
+
+        writer
+            .write_all(format!("{}\n", ClientRequest::Quit).as_bytes())
+            .await?;
+        writer.shutdown().await?;
+
+        Ok::<_, NetError>(())
+    };
+    tokio::pin!(writing);
+
+    // A connection can die without being closed, so give up on it if the
+    // server goes quiet for too long. Servers that never answer pings could
+    // just have nothing to say, so only those that have are timed out.
+    let idle = time::sleep(keepalive.timeout);
+    tokio::pin!(idle);
+    let mut ponged = false;
+
+    // Handle server messages
+    loop {
+        tokio::select! {
+            result = &mut writing => return result,
+
+            _ = &mut idle, if keepalive.enabled && ponged => return Err(NetError::TimedOut),
+
+            line = lines.next_line() => {
+                let line = match line? {
+                    Some(line) => line,
+                    None => return Ok(()),
+                };
+                idle.as_mut().reset(Instant::now() + keepalive.timeout);
+
+                let record = str::parse::<Record>(&line)
+                    .map_err(|_| NetError::Protocol(line))?;
+
+                let command = match &record.command {
+                    ChatCommands::Pong { token } => match token.parse() {
+                        Ok(sent) => {
+                            ponged = true;
+                            ClientCommands::Latency(
+                                start.elapsed().saturating_sub(Duration::from_micros(sent)),
+                            )
+                        }
+
+                        // Not one of our pings
+                        Err(_) => continue,
+                    },
+                    _ => ClientCommands::ChatCommand(record),
+                };
+
+                if send.send(command).await.is_err() {
+                    return Ok(());
+                }
+
+                waker.wake();
+            }
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use crate::net::error::NetError;
+
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChatCommands {
+    Message {
+        sender: String,
+        message: String,
+    },
+    /// A direct message sent only to us.
+    PrivateMessage {
+        sender: String,
+        message: String,
+    },
+    UserConnected {
+        name: String,
+    },
+    UserDisconnected {
+        name: String,
+    },
+    UserRenamed {
+        oldname: String,
+        newname: String,
+    },
+    /// Everyone already online, sent by the server when we join.
+    UserList {
+        names: Vec<String>,
+    },
+    RoomMessage {
+        room: String,
+        sender: String,
+        message: String,
+    },
+    UserJoined {
+        room: String,
+        name: String,
+    },
+    UserLeft {
+        room: String,
+        name: String,
+    },
+    /// Everyone already in `room`, sent by the server when we join it.
+    RoomUsers {
+        room: String,
+        names: Vec<String>,
+    },
+    /// Every room on the server, in answer to `ClientRequest::ListRooms`.
+    RoomList {
+        rooms: Vec<String>,
+    },
+    /// Answer to `ClientRequest::Ping`, with the same token.
+    Pong {
+        token: String,
+    },
+}
+
+/// Splits a line into its first word and the rest.
+fn word(s: &str) -> Result<(&str, &str), ()> {
+    s.split_once(' ').ok_or(())
+}
+
+fn names(s: &str) -> Vec<String> {
+    s.split_whitespace().map(str::to_string).collect()
+}
+
+impl FromStr for ChatCommands {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s.split_once(' ') {
+            Some((a, b)) => match a {
+                "m" | "msg" => {
+                    let (a, b) = b.split_once(' ').ok_or(())?;
+
+                    Ok(Self::Message {
+                        sender: a.to_string(),
+                        message: b.to_string(),
+                    })
+                }
+
+                "pm" => {
+                    let (a, b) = b.split_once(' ').ok_or(())?;
+
+                    Ok(Self::PrivateMessage {
+                        sender: a.to_string(),
+                        message: b.to_string(),
+                    })
+                }
+
+                "c" | "connect" => Ok(Self::UserConnected {
+                    name: b.to_string(),
+                }),
+                "d" | "disconnect" => Ok(Self::UserDisconnected {
+                    name: b.to_string(),
+                }),
+
+                "r" | "rename" => {
+                    let (a, b) = b.split_once(' ').ok_or(())?;
+
+                    Ok(Self::UserRenamed {
+                        oldname: a.to_string(),
+                        newname: b.to_string(),
+                    })
+                }
+
+                "u" | "users" => Ok(Self::UserList { names: names(b) }),
+
+                "rm" => {
+                    let (room, b) = word(b)?;
+                    let (sender, message) = word(b)?;
+
+                    Ok(Self::RoomMessage {
+                        room: room.to_string(),
+                        sender: sender.to_string(),
+                        message: message.to_string(),
+                    })
+                }
+                "j" | "join" => {
+                    let (room, name) = word(b)?;
+
+                    Ok(Self::UserJoined {
+                        room: room.to_string(),
+                        name: name.to_string(),
+                    })
+                }
+                "l" | "leave" => {
+                    let (room, name) = word(b)?;
+
+                    Ok(Self::UserLeft {
+                        room: room.to_string(),
+                        name: name.to_string(),
+                    })
+                }
+                "ru" => {
+                    // Nobody else may be in the room
+                    let (room, b) = b.split_once(' ').unwrap_or((b, ""));
+
+                    Ok(Self::RoomUsers {
+                        room: room.to_string(),
+                        names: names(b),
+                    })
+                }
+                "rl" | "rooms" => Ok(Self::RoomList { rooms: names(b) }),
+
+                "pong" => Ok(Self::Pong {
+                    token: b.to_string(),
+                }),
+
+                _ => Err(()),
+            },
+
+            None => Err(()),
+        }
+    }
+}
+
+impl ChatCommands {
+    /// The user who sent the message or who the event is about.
+    pub fn sender(&self) -> &str {
+        match self {
+            Self::Message { sender, .. }
+            | Self::PrivateMessage { sender, .. }
+            | Self::RoomMessage { sender, .. } => sender,
+            Self::UserConnected { name }
+            | Self::UserDisconnected { name }
+            | Self::UserJoined { name, .. }
+            | Self::UserLeft { name, .. } => name,
+            Self::UserRenamed { newname, .. } => newname,
+            Self::UserList { .. }
+            | Self::RoomUsers { .. }
+            | Self::RoomList { .. }
+            | Self::Pong { .. } => "",
+        }
+    }
+
+    /// The message, or a description of the event.
+    pub fn text(&self) -> String {
+        match self {
+            Self::Message { message, .. }
+            | Self::PrivateMessage { message, .. }
+            | Self::RoomMessage { message, .. } => message.clone(),
+            Self::UserConnected { name } => format!("{} connected", name),
+            Self::UserDisconnected { name } => format!("{} disconnected", name),
+            Self::UserRenamed { oldname, newname } => {
+                format!("{} changed names to {}", oldname, newname)
+            }
+            Self::UserList { names } => format!("{} online: {}", names.len(), names.join(", ")),
+            Self::UserJoined { room, name } => format!("{} joined #{}", name, room),
+            Self::UserLeft { room, name } => format!("{} left #{}", name, room),
+            Self::RoomUsers { room, names } => {
+                format!("{} in #{}: {}", names.len(), room, names.join(", "))
+            }
+            Self::RoomList { rooms } => format!("Rooms: {}", rooms.join(", ")),
+            Self::Pong { token } => format!("Pong {}", token),
+        }
+    }
+}
+
+impl fmt::Display for ChatCommands {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message { sender, message } => write!(f, "m {} {}", sender, message),
+            Self::PrivateMessage { sender, message } => write!(f, "pm {} {}", sender, message),
+            Self::UserConnected { name } => write!(f, "c {}", name),
+            Self::UserDisconnected { name } => write!(f, "d {}", name),
+            Self::UserRenamed { oldname, newname } => write!(f, "r {} {}", oldname, newname),
+            Self::UserList { names } => write!(f, "u {}", names.join(" ")),
+            Self::RoomMessage {
+                room,
+                sender,
+                message,
+            } => write!(f, "rm {} {} {}", room, sender, message),
+            Self::UserJoined { room, name } => write!(f, "j {} {}", room, name),
+            Self::UserLeft { room, name } => write!(f, "l {} {}", room, name),
+            Self::RoomUsers { room, names } if names.is_empty() => write!(f, "ru {}", room),
+            Self::RoomUsers { room, names } => write!(f, "ru {} {}", room, names.join(" ")),
+            Self::RoomList { rooms } => write!(f, "rl {}", rooms.join(" ")),
+            Self::Pong { token } => write!(f, "pong {}", token),
+        }
+    }
+}
+
+/// A chat event along with when it happened.
+///
+/// On the wire the server may give its own time for an event by prefixing the
+/// line with `@` and milliseconds since the Unix epoch, like
+/// `@1650000000000 m alice hi`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    /// When the client received the event.
+    pub time: SystemTime,
+    /// When the server says the event happened.
+    pub server_time: Option<SystemTime>,
+    pub command: ChatCommands,
+}
+
+impl Record {
+    pub fn now(command: ChatCommands) -> Self {
+        Self {
+            time: SystemTime::now(),
+            server_time: None,
+            command,
+        }
+    }
+
+    /// The server's time for the event if it gave one, otherwise when it was
+    /// received.
+    pub fn when(&self) -> SystemTime {
+        self.server_time.unwrap_or(self.time)
+    }
+}
+
+pub fn to_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+pub fn from_millis(millis: &str) -> Option<SystemTime> {
+    Some(UNIX_EPOCH + Duration::from_millis(millis.parse().ok()?))
+}
+
+/// Parses a line from the server, received now.
+impl FromStr for Record {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        let (server_time, command) = match s.strip_prefix('@') {
+            Some(s) => {
+                let (millis, command) = s.split_once(' ').ok_or(())?;
+                (Some(from_millis(millis).ok_or(())?), command)
+            }
+            None => (None, s),
+        };
+
+        Ok(Self {
+            server_time,
+            ..Self::now(command.parse()?)
+        })
+    }
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(server_time) = self.server_time {
+            write!(f, "@{} ", to_millis(server_time))?;
+        }
+
+        write!(f, "{}", self.command)
+    }
+}
+
+/// Requests sent from the client to the server, after the initial name line.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClientRequest {
+    Message {
+        message: String,
+    },
+    Rename {
+        name: String,
+    },
+    /// A direct message to just `target`.
+    PrivateMessage {
+        target: String,
+        message: String,
+    },
+    Join {
+        room: String,
+    },
+    Leave {
+        room: String,
+    },
+    /// A message to everyone in `room`, which we must have joined.
+    RoomMessage {
+        room: String,
+        message: String,
+    },
+    ListRooms,
+    /// Asks the server to answer with a `ChatCommands::Pong` carrying `token`.
+    Ping {
+        token: String,
+    },
+    /// Tells the server we are leaving, just before the connection closes.
+    Quit,
+    /// A line passed through to the server verbatim, used for `/` commands the
+    /// client doesn't know about. Doesn't round-trip through `FromStr`.
+    Raw(String),
+}
+
+impl FromStr for ClientRequest {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, ()> {
+        match s {
+            "rooms" => return Ok(Self::ListRooms),
+            "quit" => return Ok(Self::Quit),
+            _ => (),
+        }
+
+        match s.split_once(' ') {
+            Some((a, b)) => match a {
+                "m" | "msg" => Ok(Self::Message {
+                    message: b.to_string(),
+                }),
+
+                "n" | "name" => Ok(Self::Rename {
+                    name: b.to_string(),
+                }),
+
+                "p" => {
+                    let (a, b) = b.split_once(' ').ok_or(())?;
+
+                    Ok(Self::PrivateMessage {
+                        target: a.to_string(),
+                        message: b.to_string(),
+                    })
+                }
+
+                "j" | "join" => Ok(Self::Join {
+                    room: b.to_string(),
+                }),
+                "l" | "leave" => Ok(Self::Leave {
+                    room: b.to_string(),
+                }),
+                "rm" => {
+                    let (room, message) = word(b)?;
+
+                    Ok(Self::RoomMessage {
+                        room: room.to_string(),
+                        message: message.to_string(),
+                    })
+                }
+
+                "ping" => Ok(Self::Ping {
+                    token: b.to_string(),
+                }),
+
+                _ => Err(()),
+            },
+
+            None => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for ClientRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Message { message } => write!(f, "m {}", message),
+            Self::Rename { name } => write!(f, "n {}", name),
+            Self::PrivateMessage { target, message } => write!(f, "p {} {}", target, message),
+            Self::Join { room } => write!(f, "j {}", room),
+            Self::Leave { room } => write!(f, "l {}", room),
+            Self::RoomMessage { room, message } => write!(f, "rm {} {}", room, message),
+            Self::ListRooms => write!(f, "rooms"),
+            Self::Ping { token } => write!(f, "ping {}", token),
+            Self::Quit => write!(f, "quit"),
+            Self::Raw(line) => write!(f, "{}", line),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ConnectState {
+    Loading,
+    Connected,
+    Disconnect,
+    Failed,
+    /// Waiting to retry after the connection dropped or failed.
+    Reconnecting {
+        attempt: u32,
+        next_in: Duration,
+    },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ClientCommands {
+    ChatCommand(Record),
+    ConnectState(ConnectState),
+    /// Round-trip time of the latest ping.
+    Latency(Duration),
+    /// Sent just before the final `ConnectState` when the connection failed
+    /// or ended because of an error.
+    Error(NetError),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T>(command: T)
+    where
+        T: FromStr + fmt::Display + fmt::Debug + PartialEq,
+        T::Err: fmt::Debug,
+    {
+        let line = command.to_string();
+        assert_eq!(line.parse::<T>().unwrap(), command, "line: {:?}", line);
+    }
+
+    #[test]
+    fn chat_commands_round_trip() {
+        round_trip(ChatCommands::Message {
+            sender: "alice".to_string(),
+            message: "hello there, bob".to_string(),
+        });
+        round_trip(ChatCommands::PrivateMessage {
+            sender: "alice".to_string(),
+            message: "just between us".to_string(),
+        });
+        round_trip(ChatCommands::UserConnected {
+            name: "alice".to_string(),
+        });
+        round_trip(ChatCommands::UserDisconnected {
+            name: "bob".to_string(),
+        });
+        round_trip(ChatCommands::UserRenamed {
+            oldname: "bob".to_string(),
+            newname: "robert".to_string(),
+        });
+        round_trip(ChatCommands::UserList {
+            names: vec!["alice".to_string(), "bob".to_string()],
+        });
+        round_trip(ChatCommands::UserList { names: Vec::new() });
+    }
+
+    #[test]
+    fn room_commands_round_trip() {
+        round_trip(ChatCommands::RoomMessage {
+            room: "rust".to_string(),
+            sender: "alice".to_string(),
+            message: "hello there, bob".to_string(),
+        });
+        round_trip(ChatCommands::UserJoined {
+            room: "rust".to_string(),
+            name: "alice".to_string(),
+        });
+        round_trip(ChatCommands::UserLeft {
+            room: "rust".to_string(),
+            name: "bob".to_string(),
+        });
+        round_trip(ChatCommands::RoomUsers {
+            room: "rust".to_string(),
+            names: vec!["alice".to_string(), "bob".to_string()],
+        });
+        round_trip(ChatCommands::RoomUsers {
+            room: "rust".to_string(),
+            names: Vec::new(),
+        });
+        round_trip(ChatCommands::RoomList {
+            rooms: vec!["rust".to_string(), "random".to_string()],
+        });
+
+        round_trip(ClientRequest::Join {
+            room: "rust".to_string(),
+        });
+        round_trip(ClientRequest::Leave {
+            room: "rust".to_string(),
+        });
+        round_trip(ClientRequest::RoomMessage {
+            room: "rust".to_string(),
+            message: "hello there, bob".to_string(),
+        });
+        round_trip(ClientRequest::ListRooms);
+    }
+
+    #[test]
+    fn pings_round_trip() {
+        round_trip(ClientRequest::Ping {
+            token: "12345".to_string(),
+        });
+        round_trip(ChatCommands::Pong {
+            token: "12345".to_string(),
+        });
+        round_trip(ClientRequest::Quit);
+    }
+
+    #[test]
+    fn records_carry_server_time() {
+        let record = "@1650000000000 c alice".parse::<Record>().unwrap();
+        assert_eq!(
+            record.server_time,
+            Some(UNIX_EPOCH + Duration::from_secs(1_650_000_000))
+        );
+        assert_eq!(record.when(), record.server_time.unwrap());
+        assert_eq!(record.to_string(), "@1650000000000 c alice");
+
+        let record = "c alice".parse::<Record>().unwrap();
+        assert_eq!(record.server_time, None);
+        assert_eq!(record.when(), record.time);
+        assert_eq!(record.to_string(), "c alice");
+
+        assert!("@soon c alice".parse::<Record>().is_err());
+        assert!("@1650000000000".parse::<Record>().is_err());
+    }
+
+    #[test]
+    fn client_request_round_trip() {
+        round_trip(ClientRequest::Message {
+            message: "hello there, bob".to_string(),
+        });
+        round_trip(ClientRequest::Rename {
+            name: "robert".to_string(),
+        });
+        round_trip(ClientRequest::PrivateMessage {
+            target: "bob".to_string(),
+            message: "just between us".to_string(),
+        });
+    }
+
+    #[test]
+    fn client_request_encoding() {
+        let message = ClientRequest::Message {
+            message: "/not a command".to_string(),
+        };
+        assert_eq!(message.to_string(), "m /not a command");
+
+        let rename = ClientRequest::Rename {
+            name: "robert".to_string(),
+        };
+        assert_eq!(rename.to_string(), "n robert");
+
+        assert_eq!(ClientRequest::Raw("x y".to_string()).to_string(), "x y");
+    }
+
+    #[test]
+    fn long_names_decode() {
+        assert_eq!(
+            "msg alice hi".parse::<ChatCommands>(),
+            Ok(ChatCommands::Message {
+                sender: "alice".to_string(),
+                message: "hi".to_string(),
+            })
+        );
+        assert_eq!(
+            "name robert".parse::<ClientRequest>(),
+            Ok(ClientRequest::Rename {
+                name: "robert".to_string(),
+            })
+        );
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        assert!("m alice".parse::<ChatCommands>().is_err());
+        assert!("x alice".parse::<ChatCommands>().is_err());
+        assert!("c".parse::<ChatCommands>().is_err());
+        assert!("pm alice".parse::<ChatCommands>().is_err());
+        assert!("m".parse::<ClientRequest>().is_err());
+        assert!("p bob".parse::<ClientRequest>().is_err());
+        assert!("rm rust alice".parse::<ChatCommands>().is_err());
+        assert!("j rust".parse::<ChatCommands>().is_err());
+        assert!("rm rust".parse::<ClientRequest>().is_err());
+        assert!("x y".parse::<ClientRequest>().is_err());
+    }
+}
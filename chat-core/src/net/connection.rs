@@ -0,0 +1,239 @@
+use crate::history::Retention;
+
+use std::time::Duration;
+
+/// How the network task retries after a connection drops or fails.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct ReconnectPolicy {
+    pub enabled: bool,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many attempts in a row, `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_attempts: Some(10),
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Whether another attempt should be made, counting from 1.
+    pub fn should_retry(&self, attempt: u32) -> bool {
+        self.enabled && self.max_attempts.is_none_or(|max| attempt <= max)
+    }
+
+    /// Delay before the given attempt, doubling each time up to `max_delay`
+    /// and then randomly shortened by up to half so clients don't retry in step.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self
+            .initial_delay
+            .checked_mul(factor)
+            .map_or(self.max_delay, |d| d.min(self.max_delay));
+
+        delay.mul_f64(rand::random_range(0.5..=1.0))
+    }
+}
+
+/// How the server is pinged to check the connection is still alive.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, PartialEq)]
+#[serde(default)]
+pub struct Keepalive {
+    pub enabled: bool,
+    pub interval: Duration,
+    /// Give up on the connection after hearing nothing from the server for
+    /// this long, which should be a few times `interval`. Only servers that
+    /// have answered a ping are given up on.
+    pub timeout: Duration,
+}
+
+impl Default for Keepalive {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(90),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[serde(default)]
+pub struct ConnectionData {
+    server: String,
+    name: String,
+    reconnect: ReconnectPolicy,
+    keepalive: Keepalive,
+    tls: bool,
+    /// Fingerprint of the certificate trusted for this server, when using TLS.
+    fingerprint: Option<String>,
+    retention: Retention,
+    /// Don't notify about anything on this server.
+    muted: bool,
+    /// People whose messages are dropped.
+    ignored: Vec<String>,
+}
+
+impl Default for ConnectionData {
+    fn default() -> Self {
+        Self {
+            server: "127.0.0.1:6078".to_string(),
+            name: "nobody".to_string(),
+            reconnect: ReconnectPolicy::default(),
+            keepalive: Keepalive::default(),
+            tls: false,
+            fingerprint: None,
+            retention: Retention::default(),
+            muted: false,
+            ignored: Vec::new(),
+        }
+    }
+}
+
+impl ConnectionData {
+    pub fn new(server: &str, name: &str) -> Self {
+        Self {
+            server: server.to_string(),
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn server(&self) -> &String {
+        &self.server
+    }
+
+    pub fn set_server(&mut self, server: &str) {
+        self.server = server.to_string()
+    }
+
+    pub fn name(&self) -> &String {
+        &self.name
+    }
+
+    pub fn set_name(&mut self, name: &str) {
+        self.name = name.to_string()
+    }
+
+    pub fn reconnect(&self) -> &ReconnectPolicy {
+        &self.reconnect
+    }
+
+    pub fn set_reconnect(&mut self, reconnect: ReconnectPolicy) {
+        self.reconnect = reconnect
+    }
+
+    pub fn keepalive(&self) -> &Keepalive {
+        &self.keepalive
+    }
+
+    pub fn set_keepalive(&mut self, keepalive: Keepalive) {
+        self.keepalive = keepalive
+    }
+
+    pub fn tls(&self) -> bool {
+        self.tls
+    }
+
+    pub fn set_tls(&mut self, tls: bool) {
+        self.tls = tls
+    }
+
+    pub fn fingerprint(&self) -> Option<&str> {
+        self.fingerprint.as_deref()
+    }
+
+    pub fn set_fingerprint(&mut self, fingerprint: Option<String>) {
+        self.fingerprint = fingerprint
+    }
+
+    pub fn retention(&self) -> &Retention {
+        &self.retention
+    }
+
+    pub fn set_retention(&mut self, retention: Retention) {
+        self.retention = retention
+    }
+
+    pub fn muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted
+    }
+
+    pub fn ignored(&self) -> &[String] {
+        &self.ignored
+    }
+
+    pub fn set_ignored(&mut self, ignored: Vec<String>) {
+        self.ignored = ignored
+    }
+
+    pub fn is_ignored(&self, name: &str) -> bool {
+        self.ignored.iter().any(|ignored| ignored == name)
+    }
+
+    /// Ignores `name`, returning whether they weren't already.
+    pub fn ignore(&mut self, name: &str) -> bool {
+        let new = !self.is_ignored(name);
+        if new {
+            self.ignored.push(name.to_string());
+        }
+        new
+    }
+
+    /// Stops ignoring `name`, returning whether they were.
+    pub fn unignore(&mut self, name: &str) -> bool {
+        let len = self.ignored.len();
+        self.ignored.retain(|ignored| ignored != name);
+        self.ignored.len() != len
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_backs_off_with_jitter() {
+        let policy = ReconnectPolicy {
+            initial_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(30),
+            ..Default::default()
+        };
+
+        for (attempt, full) in [(1, 2), (2, 4), (3, 8), (4, 16), (5, 30), (40, 30)] {
+            let full = Duration::from_secs(full);
+            let delay = policy.delay(attempt);
+
+            assert!(delay >= full / 2 && delay <= full, "attempt {}", attempt);
+        }
+    }
+
+    #[test]
+    fn retries_are_limited() {
+        let mut policy = ReconnectPolicy {
+            max_attempts: Some(3),
+            ..Default::default()
+        };
+
+        assert!(policy.should_retry(3));
+        assert!(!policy.should_retry(4));
+
+        policy.max_attempts = None;
+        assert!(policy.should_retry(1000));
+
+        policy.enabled = false;
+        assert!(!policy.should_retry(1));
+    }
+}
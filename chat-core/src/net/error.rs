@@ -0,0 +1,72 @@
+use std::{fmt, io};
+
+/// Why a connection to a server failed or ended.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetError {
+    /// The server address couldn't be resolved.
+    Resolve(String),
+    Refused,
+    Reset,
+    /// The server sent a line that isn't part of the protocol.
+    Protocol(String),
+    InvalidUtf8,
+    /// Nothing was heard from the server for too long.
+    TimedOut,
+    Tls(String),
+    /// The server's certificate hasn't been trusted yet, holds its fingerprint.
+    UntrustedCertificate(String),
+    /// The server's certificate doesn't match the one trusted before.
+    CertificateChanged {
+        pinned: String,
+        presented: String,
+    },
+    Io(String),
+}
+
+impl NetError {
+    /// Errors that need the user to step in, so retrying is pointless.
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            Self::UntrustedCertificate(_) | Self::CertificateChanged { .. }
+        )
+    }
+}
+
+impl From<io::Error> for NetError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::ConnectionRefused => Self::Refused,
+            io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof => Self::Reset,
+            io::ErrorKind::InvalidData => Self::InvalidUtf8,
+            io::ErrorKind::TimedOut => Self::TimedOut,
+            _ => Self::Io(error.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for NetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Resolve(server) => write!(f, "Couldn't resolve {}", server),
+            Self::Refused => write!(f, "Connection refused"),
+            Self::Reset => write!(f, "Connection reset by server"),
+            Self::Protocol(line) => write!(f, "Server sent an invalid line: {:?}", line),
+            Self::InvalidUtf8 => write!(f, "Server sent invalid UTF-8"),
+            Self::TimedOut => write!(f, "Server stopped responding"),
+            Self::Tls(error) => write!(f, "TLS error: {}", error),
+            Self::UntrustedCertificate(fingerprint) => {
+                write!(f, "Server certificate {} isn't trusted", fingerprint)
+            }
+            Self::CertificateChanged { pinned, presented } => write!(
+                f,
+                "Server certificate changed from {} to {}, refusing to connect",
+                pinned, presented
+            ),
+            Self::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
@@ -0,0 +1,7 @@
+pub mod client;
+pub mod commands;
+pub mod connection;
+pub mod error;
+pub mod tls;
+pub mod transport;
+pub mod uri;
@@ -0,0 +1,212 @@
+use crate::net::error::NetError;
+
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{self, CryptoProvider},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        ClientConfig, DigitallySignedStruct, SignatureScheme,
+    },
+    TlsConnector,
+};
+
+/// Upgrades `stream` to TLS, trusting the server only if its certificate
+/// matches the `pinned` fingerprint.
+///
+/// Servers are expected to use self-signed certificates, so no CA chain is
+/// checked. An unpinned certificate is reported with its fingerprint so the
+/// user can decide whether to trust it.
+pub async fn connect(
+    stream: TcpStream,
+    host: &str,
+    pinned: Option<&str>,
+) -> Result<TlsStream<TcpStream>, NetError> {
+    let provider = Arc::new(crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| NetError::Tls(e.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(PinnedVerifier { provider }))
+        .with_no_client_auth();
+
+    let server_name =
+        ServerName::try_from(host.to_owned()).map_err(|_| NetError::Resolve(host.to_owned()))?;
+
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+        .map_err(|e| NetError::Tls(e.to_string()))?;
+
+    let presented = match stream.get_ref().1.peer_certificates() {
+        Some([cert, ..]) => fingerprint(cert),
+        _ => return Err(NetError::Tls("Server sent no certificate".to_string())),
+    };
+
+    match pinned {
+        Some(pinned) if pinned == presented => Ok(stream),
+        Some(pinned) => Err(NetError::CertificateChanged {
+            pinned: pinned.to_owned(),
+            presented,
+        }),
+        None => Err(NetError::UntrustedCertificate(presented)),
+    }
+}
+
+/// SHA-256 fingerprint of a certificate, as colon separated hex.
+pub fn fingerprint(cert: &[u8]) -> String {
+    Sha256::digest(cert)
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// Accepts any certificate so it can be checked against the pinned
+/// fingerprint once the handshake is done, while still making sure the server
+/// holds the certificate's key.
+#[derive(Debug)]
+struct PinnedVerifier {
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.provider.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider
+            .signature_verification_algorithms
+            .supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::PrivateKeyDer, ServerConfig},
+        TlsAcceptor,
+    };
+
+    /// Starts a server with a fresh self-signed certificate which accepts
+    /// connections forever, returning its port and certificate fingerprint.
+    async fn self_signed_server() -> (u16, String) {
+        let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert = key.cert.der().clone();
+        let fingerprint = fingerprint(&cert);
+
+        let config =
+            ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()
+                .unwrap()
+                .with_no_client_auth()
+                .with_single_cert(
+                    vec![cert],
+                    PrivateKeyDer::try_from(key.signing_key.serialize_der()).unwrap(),
+                )
+                .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    if let Ok(mut stream) = acceptor.accept(stream).await {
+                        let _ = stream.write_all(b"c alice\n").await;
+                        let _ = stream.read(&mut [0; 64]).await;
+                    }
+                });
+            }
+        });
+
+        (port, fingerprint)
+    }
+
+    async fn connect_to(port: u16, pinned: Option<&str>) -> Result<TlsStream<TcpStream>, NetError> {
+        let stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+        connect(stream, "localhost", pinned).await
+    }
+
+    #[tokio::test]
+    async fn unknown_certificate_is_reported() {
+        let (port, fingerprint) = self_signed_server().await;
+
+        assert_eq!(
+            connect_to(port, None).await.err(),
+            Some(NetError::UntrustedCertificate(fingerprint))
+        );
+    }
+
+    #[tokio::test]
+    async fn pinned_certificate_is_trusted() {
+        let (port, fingerprint) = self_signed_server().await;
+
+        let mut stream = connect_to(port, Some(&fingerprint)).await.unwrap();
+        let mut line = [0; 8];
+        stream.read_exact(&mut line).await.unwrap();
+
+        assert_eq!(&line, b"c alice\n");
+    }
+
+    #[tokio::test]
+    async fn changed_certificate_is_refused() {
+        let (port, fingerprint) = self_signed_server().await;
+        let pinned = "00:".repeat(31) + "00";
+
+        assert_eq!(
+            connect_to(port, Some(&pinned)).await.err(),
+            Some(NetError::CertificateChanged {
+                pinned,
+                presented: fingerprint,
+            })
+        );
+    }
+}
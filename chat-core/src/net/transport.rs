@@ -0,0 +1,245 @@
+use crate::net::{connection::ConnectionData, error::NetError, tls};
+
+use std::{future::Future, pin::Pin};
+use tokio::{
+    io::{self, AsyncBufRead, AsyncRead, AsyncWrite, BufReader},
+    net::{self, TcpStream},
+    sync::mpsc,
+};
+
+pub type Reader = Pin<Box<dyn AsyncBufRead + Send>>;
+pub type Writer = Pin<Box<dyn AsyncWrite + Send>>;
+pub type Connecting<'a> =
+    Pin<Box<dyn Future<Output = Result<(Reader, Writer), NetError>> + Send + 'a>>;
+
+/// A way of reaching a server, opening a fresh connection on every call to
+/// `connect` so it can be reused when reconnecting.
+pub trait Transport: Send + Sync {
+    fn connect(&self) -> Connecting<'_>;
+}
+
+/// Picks the transport for a connection's server address.
+///
+/// Addresses starting with `unix:` are paths to a Unix domain socket, anything
+/// else is a `host[:port]` reached over TCP, with TLS if enabled.
+pub fn for_connection(connection: &ConnectionData) -> Box<dyn Transport> {
+    if let Some(path) = connection.server().strip_prefix("unix:") {
+        Box::new(Unix::new(path))
+    } else if connection.tls() {
+        Box::new(Tls::new(
+            connection.server(),
+            connection.fingerprint().map(str::to_owned),
+        ))
+    } else {
+        Box::new(Tcp::new(connection.server()))
+    }
+}
+
+fn split<S>(stream: S) -> (Reader, Writer)
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, writer) = io::split(stream);
+    (Box::pin(BufReader::new(reader)), Box::pin(writer))
+}
+
+pub struct Tcp {
+    address: String,
+}
+
+impl Tcp {
+    /// Port used when the address doesn't give one.
+    pub const DEFAULT_PORT: u16 = 6078;
+
+    pub fn new(address: &str) -> Self {
+        let address = if address.contains(':') {
+            address.to_owned()
+        } else {
+            format!("{}:{}", address, Self::DEFAULT_PORT)
+        };
+
+        Self { address }
+    }
+
+    /// The address without its port, or brackets for IPv6.
+    fn host(&self) -> &str {
+        self.address
+            .rsplit_once(':')
+            .map_or(&self.address[..], |(host, _)| host)
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+    }
+
+    async fn stream(&self) -> Result<TcpStream, NetError> {
+        let addrs = net::lookup_host(&self.address)
+            .await
+            .map_err(|_| NetError::Resolve(self.address.clone()))?
+            .collect::<Vec<_>>();
+
+        if addrs.is_empty() {
+            return Err(NetError::Resolve(self.address.clone()));
+        }
+
+        Ok(TcpStream::connect(&addrs[..]).await?)
+    }
+}
+
+impl Transport for Tcp {
+    fn connect(&self) -> Connecting<'_> {
+        Box::pin(async move { Ok(split(self.stream().await?)) })
+    }
+}
+
+pub struct Tls {
+    tcp: Tcp,
+    pinned: Option<String>,
+}
+
+impl Tls {
+    pub fn new(address: &str, pinned: Option<String>) -> Self {
+        Self {
+            tcp: Tcp::new(address),
+            pinned,
+        }
+    }
+}
+
+impl Transport for Tls {
+    fn connect(&self) -> Connecting<'_> {
+        Box::pin(async move {
+            let stream = self.tcp.stream().await?;
+            let stream = tls::connect(stream, self.tcp.host(), self.pinned.as_deref()).await?;
+
+            Ok(split(stream))
+        })
+    }
+}
+
+pub struct Unix {
+    path: String,
+}
+
+impl Unix {
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.to_owned(),
+        }
+    }
+}
+
+impl Transport for Unix {
+    #[cfg(unix)]
+    fn connect(&self) -> Connecting<'_> {
+        Box::pin(async move {
+            let stream = net::UnixStream::connect(&self.path).await?;
+            Ok(split(stream))
+        })
+    }
+
+    #[cfg(not(unix))]
+    fn connect(&self) -> Connecting<'_> {
+        Box::pin(async move {
+            Err(NetError::Io(
+                "Unix sockets aren't supported on this platform".to_string(),
+            ))
+        })
+    }
+}
+
+/// Connects to a server running in the same process, handing it the other end
+/// of an in-memory pipe for every connection. Mostly useful for tests.
+pub struct Memory {
+    listener: mpsc::UnboundedSender<io::DuplexStream>,
+}
+
+impl Memory {
+    /// Returns the transport along with the receiving end the server accepts
+    /// connections from. Connecting is refused once that is dropped.
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<io::DuplexStream>) {
+        let (listener, accept) = mpsc::unbounded_channel();
+        (Self { listener }, accept)
+    }
+}
+
+impl Transport for Memory {
+    fn connect(&self) -> Connecting<'_> {
+        Box::pin(async move {
+            let (client, server) = io::duplex(1024);
+            self.listener.send(server).map_err(|_| NetError::Refused)?;
+
+            Ok(split(client))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+    /// Sends back the first line received on `stream`.
+    async fn echo<S: AsyncRead + AsyncWrite>(stream: S) {
+        let (reader, mut writer) = io::split(stream);
+        let mut line = String::new();
+
+        BufReader::new(reader).read_line(&mut line).await.unwrap();
+        writer.write_all(line.as_bytes()).await.unwrap();
+    }
+
+    async fn echo_line(transport: &dyn Transport) -> String {
+        let (mut reader, mut writer) = transport.connect().await.unwrap();
+        writer.write_all(b"m hello\n").await.unwrap();
+
+        let mut line = String::new();
+        reader.read_line(&mut line).await.unwrap();
+        line
+    }
+
+    #[test]
+    fn default_port_is_added() {
+        assert_eq!(Tcp::new("example.com").address, "example.com:6078");
+        assert_eq!(Tcp::new("example.com:1234").address, "example.com:1234");
+        assert_eq!(Tcp::new("[::1]:1234").host(), "::1");
+    }
+
+    #[tokio::test]
+    async fn memory_pipes_connect() {
+        let (transport, mut accept) = Memory::new();
+
+        tokio::spawn(async move {
+            while let Some(stream) = accept.recv().await {
+                echo(stream).await;
+            }
+        });
+
+        assert_eq!(echo_line(&transport).await, "m hello\n");
+        assert_eq!(echo_line(&transport).await, "m hello\n");
+    }
+
+    #[tokio::test]
+    async fn memory_refuses_without_server() {
+        let (transport, accept) = Memory::new();
+        drop(accept);
+
+        assert_eq!(transport.connect().await.err(), Some(NetError::Refused));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn unix_sockets_connect() {
+        let path = std::env::temp_dir().join(format!("chat-core-test-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = net::UnixListener::bind(&path).unwrap();
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            echo(stream).await;
+        });
+
+        let transport = Unix::new(path.to_str().unwrap());
+        assert_eq!(echo_line(&transport).await, "m hello\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
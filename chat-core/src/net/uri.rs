@@ -0,0 +1,200 @@
+//! `chat://` links to a server, which open the client straight to it.
+//!
+//! A link looks like `chat://name@host:port?tls&fingerprint=ab:cd`, where
+//! only the host is needed. The name is percent-encoded, the port defaults to
+//! [`Tcp::DEFAULT_PORT`], and the query turns on TLS and pins the server's
+//! certificate.
+
+use super::{connection::ConnectionData, transport::Tcp};
+
+use std::{fmt, str::FromStr};
+
+pub const SCHEME: &str = "chat://";
+
+/// Why a link couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum UriError {
+    /// The link doesn't start with `chat://`.
+    Scheme,
+    Host,
+    Port(String),
+    /// The name is empty, has spaces in it, or isn't valid percent-encoding.
+    Name(String),
+}
+
+impl fmt::Display for UriError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scheme => write!(f, "Links must start with {}", SCHEME),
+            Self::Host => write!(f, "Link has no server"),
+            Self::Port(port) => write!(f, "Invalid port {:?}", port),
+            Self::Name(name) => write!(f, "Invalid name {:?}", name),
+        }
+    }
+}
+
+impl std::error::Error for UriError {}
+
+impl FromStr for ConnectionData {
+    type Err = UriError;
+
+    fn from_str(uri: &str) -> Result<Self, UriError> {
+        let rest = uri.strip_prefix(SCHEME).ok_or(UriError::Scheme)?;
+        let (rest, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let rest = rest.trim_end_matches('/');
+
+        let (name, address) = match rest.rsplit_once('@') {
+            Some((name, address)) => (Some(decode(name)?), address),
+            None => (None, rest),
+        };
+
+        // IPv6 hosts are in brackets, as their colons aren't the port's
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) if !port.ends_with(']') => (host, Some(port)),
+            _ => (address, None),
+        };
+        if host.is_empty() || host == "[]" {
+            return Err(UriError::Host);
+        }
+        let port = match port {
+            Some(port) => port
+                .parse::<u16>()
+                .map_err(|_| UriError::Port(port.to_string()))?,
+            None => Tcp::DEFAULT_PORT,
+        };
+
+        let name = name.unwrap_or_else(|| Self::default().name().clone());
+        let mut connection = Self::new(&format!("{}:{}", host, port), &name);
+
+        for param in query.split('&') {
+            match param.split_once('=') {
+                Some(("fingerprint", fingerprint)) => {
+                    connection.set_tls(true);
+                    connection.set_fingerprint(Some(fingerprint.to_string()));
+                }
+                _ if param == "tls" => connection.set_tls(true),
+
+                // Left for newer clients
+                _ => (),
+            }
+        }
+
+        Ok(connection)
+    }
+}
+
+impl ConnectionData {
+    /// A link that opens this server with the same name and certificate.
+    pub fn uri(&self) -> String {
+        let mut uri = format!("{}{}@{}", SCHEME, encode(self.name()), self.server());
+
+        match (self.tls(), self.fingerprint()) {
+            (true, Some(fingerprint)) => uri += &format!("?tls&fingerprint={}", fingerprint),
+            (true, None) => uri += "?tls",
+            _ => (),
+        }
+
+        uri
+    }
+}
+
+/// Whether `c` can be in a link's name without being encoded.
+fn unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"-._~".contains(&c)
+}
+
+fn encode(name: &str) -> String {
+    name.bytes()
+        .map(|c| {
+            if unreserved(c) {
+                (c as char).to_string()
+            } else {
+                format!("%{:02X}", c)
+            }
+        })
+        .collect()
+}
+
+fn decode(name: &str) -> Result<String, UriError> {
+    let invalid = || UriError::Name(name.to_string());
+
+    let mut bytes = Vec::with_capacity(name.len());
+    let mut rest = name.as_bytes();
+    while let Some((&c, after)) = rest.split_first() {
+        if c == b'%' {
+            let hex = after.get(..2).ok_or_else(invalid)?;
+            let hex = std::str::from_utf8(hex).map_err(|_| invalid())?;
+            bytes.push(u8::from_str_radix(hex, 16).map_err(|_| invalid())?);
+            rest = &after[2..];
+        } else {
+            bytes.push(c);
+            rest = after;
+        }
+    }
+
+    // Names are single words on the wire
+    match String::from_utf8(bytes) {
+        Ok(name) if !name.is_empty() && !name.contains(char::is_whitespace) => Ok(name),
+        _ => Err(invalid()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(uri: &str) -> Result<ConnectionData, UriError> {
+        uri.parse()
+    }
+
+    #[test]
+    fn links_are_read() {
+        let connection = parse("chat://alice@example.com:7000").unwrap();
+        assert_eq!(connection.server(), "example.com:7000");
+        assert_eq!(connection.name(), "alice");
+        assert!(!connection.tls());
+
+        let connection = parse("chat://example.com/").unwrap();
+        assert_eq!(connection.server(), "example.com:6078");
+        assert_eq!(connection.name(), ConnectionData::default().name());
+
+        let connection = parse("chat://j%C3%B6rg@[::1]?tls&fingerprint=ab:cd").unwrap();
+        assert_eq!(connection.server(), "[::1]:6078");
+        assert_eq!(connection.name(), "jörg");
+        assert!(connection.tls());
+        assert_eq!(connection.fingerprint(), Some("ab:cd"));
+
+        let connection = parse("chat://bob@[::1]:7000?tls&future").unwrap();
+        assert_eq!(connection.server(), "[::1]:7000");
+        assert!(connection.tls());
+    }
+
+    #[test]
+    fn bad_links_are_refused() {
+        assert_eq!(parse("http://example.com").unwrap_err(), UriError::Scheme);
+        assert_eq!(parse("chat://alice@").unwrap_err(), UriError::Host);
+        assert_eq!(
+            parse("chat://example.com:port").unwrap_err(),
+            UriError::Port("port".to_string())
+        );
+        assert_eq!(
+            parse("chat://two%20words@example.com").unwrap_err(),
+            UriError::Name("two%20words".to_string())
+        );
+        assert_eq!(
+            parse("chat://%F@example.com").unwrap_err(),
+            UriError::Name("%F".to_string())
+        );
+    }
+
+    #[test]
+    fn links_round_trip() {
+        for uri in [
+            "chat://alice@example.com:6078",
+            "chat://j%C3%B6rg@[::1]:7000?tls",
+            "chat://bob@example.com:6078?tls&fingerprint=ab:cd",
+        ] {
+            assert_eq!(parse(uri).unwrap().uri(), uri);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use crate::{mention, net::commands::ChatCommands, net::connection::ConnectionData};
+
+/// Something worth telling the user about while they may be looking
+/// elsewhere.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+}
+
+/// Somewhere to show notifications.
+pub trait Notifier {
+    fn notify(&mut self, notification: Notification);
+}
+
+/// Shows nothing, for platforms without a notification service.
+pub struct Silent;
+
+impl Notifier for Silent {
+    fn notify(&mut self, _notification: Notification) {}
+}
+
+impl Default for Box<dyn Notifier> {
+    fn default() -> Self {
+        Box::new(Silent)
+    }
+}
+
+/// Keeps notifications rather than showing them, for tests.
+impl Notifier for Vec<Notification> {
+    fn notify(&mut self, notification: Notification) {
+        self.push(notification);
+    }
+}
+
+/// Shows notifications through the freedesktop notification service, over
+/// D-Bus.
+#[cfg(all(unix, not(target_os = "macos")))]
+pub struct Desktop {
+    send: std::sync::mpsc::Sender<Notification>,
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl Desktop {
+    pub fn spawn() -> Self {
+        let (send, recv) = std::sync::mpsc::channel::<Notification>();
+
+        // Talking to D-Bus blocks, so keep it off the UI thread
+        std::thread::spawn(move || {
+            for notification in recv {
+                let shown = notify_rust::Notification::new()
+                    .appname(crate::APP_NAME)
+                    .summary(&notification.summary)
+                    .body(&notification.body)
+                    .show();
+
+                if let Err(e) = shown {
+                    tracing::warn!("Couldn't show notification: {}", e);
+                }
+            }
+        });
+
+        Self { send }
+    }
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+impl Notifier for Desktop {
+    fn notify(&mut self, notification: Notification) {
+        let _ = self.send.send(notification);
+    }
+}
+
+/// The platform's notification service, if we know how to use it.
+pub fn desktop() -> Box<dyn Notifier> {
+    #[cfg(all(unix, not(target_os = "macos")))]
+    return Box::new(Desktop::spawn());
+
+    #[cfg(not(all(unix, not(target_os = "macos"))))]
+    return Box::new(Silent);
+}
+
+/// What to be notified about, on every server.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct NotifySettings {
+    pub do_not_disturb: bool,
+    /// Words besides our name that are worth a notification.
+    pub keywords: Vec<String>,
+}
+
+impl NotifySettings {
+    /// The notification for `command` arriving over `connection`, if it's
+    /// one we want.
+    pub fn notification(
+        &self,
+        connection: &ConnectionData,
+        command: &ChatCommands,
+    ) -> Option<Notification> {
+        if self.do_not_disturb || connection.muted() {
+            return None;
+        }
+
+        let (sender, message, place) = match command {
+            ChatCommands::PrivateMessage { sender, message } => {
+                return Some(Notification {
+                    summary: format!("{} messaged you", sender),
+                    body: message.clone(),
+                });
+            }
+            ChatCommands::Message { sender, message } => {
+                (sender, message, format!("on {}", connection.server()))
+            }
+            ChatCommands::RoomMessage {
+                room,
+                sender,
+                message,
+            } => (sender, message, format!("in #{}", room)),
+            _ => return None,
+        };
+
+        if sender == connection.name() {
+            return None;
+        }
+
+        let summary = if mention::mentions(message, connection.name()) {
+            format!("{} mentioned you {}", sender, place)
+        } else {
+            let keyword = self
+                .keywords
+                .iter()
+                .find(|keyword| mention::mentions(message, keyword))?;
+
+            format!("{} mentioned \"{}\" {}", sender, keyword, place)
+        };
+
+        Some(Notification {
+            summary,
+            body: message.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notify_all(settings: &NotifySettings, connection: &ConnectionData) -> Vec<Notification> {
+        let lines = [
+            "m bob hello everyone",
+            "m bob are you there, alice?",
+            "m alice I'm here",
+            "rm rust carol the release is out",
+            "rm rust carol ALICE look",
+            "pm dave just us",
+            "c erin",
+        ];
+
+        let mut notifier = Vec::new();
+        for line in lines {
+            if let Some(notification) = settings.notification(connection, &line.parse().unwrap()) {
+                notifier.notify(notification);
+            }
+        }
+
+        notifier
+    }
+
+    #[test]
+    fn mentions_keywords_and_direct_messages_notify() {
+        let connection = ConnectionData::new("example.com:6078", "alice");
+        let settings = NotifySettings {
+            keywords: vec!["release".to_string()],
+            ..Default::default()
+        };
+
+        let summaries = notify_all(&settings, &connection)
+            .into_iter()
+            .map(|n| n.summary)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summaries,
+            vec![
+                "bob mentioned you on example.com:6078",
+                "carol mentioned \"release\" in #rust",
+                "carol mentioned you in #rust",
+                "dave messaged you",
+            ]
+        );
+    }
+
+    #[test]
+    fn muting_and_do_not_disturb_silence_everything() {
+        let mut connection = ConnectionData::new("example.com:6078", "alice");
+        let mut settings = NotifySettings {
+            do_not_disturb: true,
+            ..Default::default()
+        };
+        assert!(notify_all(&settings, &connection).is_empty());
+
+        settings.do_not_disturb = false;
+        connection.set_muted(true);
+        assert!(notify_all(&settings, &connection).is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{history, net::commands::ClientRequest};
+
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Where outboxes are kept, next to the chat histories.
+pub fn dir() -> Option<PathBuf> {
+    history::dir().map(|dir| dir.with_file_name("outbox"))
+}
+
+/// Requests written while disconnected, kept on disk until they can be sent.
+#[derive(Default)]
+pub struct Outbox {
+    /// Only kept in memory if `None`.
+    path: Option<PathBuf>,
+    pending: Vec<ClientRequest>,
+}
+
+impl Outbox {
+    /// Opens the outbox for `server` in the default directory.
+    pub fn open(server: &str) -> io::Result<Self> {
+        let dir = dir().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No home directory for outbox")
+        })?;
+
+        Self::open_in(&dir, server)
+    }
+
+    pub fn open_in(dir: &Path, server: &str) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let path = history::file_path(dir, server, "txt");
+
+        let pending = match fs::read_to_string(&path) {
+            // Lines are stored as sent, and `/` commands don't parse back
+            Ok(text) => text
+                .lines()
+                .map(|line| {
+                    line.parse()
+                        .unwrap_or_else(|_| ClientRequest::Raw(line.to_string()))
+                })
+                .collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            pending,
+        })
+    }
+
+    pub fn pending(&self) -> &[ClientRequest] {
+        &self.pending
+    }
+
+    pub fn push(&mut self, request: ClientRequest) {
+        self.pending.push(request);
+    }
+
+    pub fn remove(&mut self, i: usize) -> ClientRequest {
+        self.pending.remove(i)
+    }
+
+    /// Empties the outbox, returning everything in the order it was written.
+    pub fn take(&mut self) -> Vec<ClientRequest> {
+        std::mem::take(&mut self.pending)
+    }
+
+    /// Writes the outbox back to disk after changing it.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if self.pending.is_empty() {
+            return match fs::remove_file(path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+                _ => Ok(()),
+            };
+        }
+
+        let temp = path.with_extension("txt.tmp");
+        let mut file = fs::File::create(&temp)?;
+        for request in &self.pending {
+            writeln!(file, "{}", request)?;
+        }
+        fs::rename(temp, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(text: &str) -> ClientRequest {
+        ClientRequest::Message {
+            message: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn pending_requests_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+
+        let mut outbox = Outbox::open_in(dir.path(), "example.com:6078").unwrap();
+        outbox.push(message("first"));
+        outbox.push(ClientRequest::Raw("me waves".to_string()));
+        outbox.push(message("second"));
+        outbox.remove(2);
+        outbox.save().unwrap();
+
+        let mut outbox = Outbox::open_in(dir.path(), "example.com:6078").unwrap();
+        assert_eq!(
+            outbox.pending(),
+            [message("first"), ClientRequest::Raw("me waves".to_string())]
+        );
+
+        assert_eq!(outbox.take().len(), 2);
+        outbox.save().unwrap();
+
+        let outbox = Outbox::open_in(dir.path(), "example.com:6078").unwrap();
+        assert!(outbox.pending().is_empty());
+    }
+}
@@ -0,0 +1,168 @@
+use crate::net::commands::ChatCommands;
+
+use std::collections::BTreeSet;
+
+/// Who is online on a server, or in one of its rooms, as far as the events
+/// seen so far tell.
+#[derive(Default, Debug)]
+pub struct Roster {
+    /// The room tracked, or the whole server if `None`.
+    room: Option<String>,
+    online: BTreeSet<String>,
+}
+
+impl Roster {
+    pub fn for_room(room: &str) -> Self {
+        Self {
+            room: Some(room.to_string()),
+            online: BTreeSet::new(),
+        }
+    }
+
+    fn is_room(&self, room: &str) -> bool {
+        self.room.as_deref() == Some(room)
+    }
+
+    /// Updates presence from an event received from the server.
+    pub fn apply(&mut self, command: &ChatCommands) {
+        match command {
+            ChatCommands::UserConnected { name } => {
+                if self.room.is_none() {
+                    self.online.insert(name.clone());
+                }
+            }
+            ChatCommands::UserDisconnected { name } => {
+                self.online.remove(name);
+            }
+            ChatCommands::UserRenamed { oldname, newname } => {
+                if self.online.remove(oldname) || self.room.is_none() {
+                    self.online.insert(newname.clone());
+                }
+            }
+            ChatCommands::UserList { names } => {
+                if self.room.is_none() {
+                    self.online = names.iter().cloned().collect();
+                }
+            }
+
+            // Anyone talking must be online, even if we missed them joining
+            ChatCommands::Message { sender, .. } | ChatCommands::PrivateMessage { sender, .. } => {
+                if self.room.is_none() && !self.online.contains(sender) {
+                    self.online.insert(sender.clone());
+                }
+            }
+
+            ChatCommands::RoomMessage { room, sender, .. }
+            | ChatCommands::UserJoined { room, name: sender } => {
+                if (self.room.is_none() || self.is_room(room)) && !self.online.contains(sender) {
+                    self.online.insert(sender.clone());
+                }
+            }
+            ChatCommands::UserLeft { room, name } => {
+                if self.is_room(room) {
+                    self.online.remove(name);
+                }
+            }
+            ChatCommands::RoomUsers { room, names } => {
+                if self.is_room(room) {
+                    self.online = names.iter().cloned().collect();
+                } else if self.room.is_none() {
+                    self.online.extend(names.iter().cloned());
+                }
+            }
+            ChatCommands::RoomList { .. } | ChatCommands::Pong { .. } => (),
+        }
+    }
+
+    /// Forgets everyone, for when the connection is lost.
+    pub fn clear(&mut self) {
+        self.online.clear();
+    }
+
+    pub fn users(&self) -> impl Iterator<Item = &str> {
+        self.online.iter().map(String::as_str)
+    }
+
+    pub fn len(&self) -> usize {
+        self.online.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.online.is_empty()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.online.contains(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn apply_all(roster: &mut Roster, lines: &[&str]) {
+        for line in lines {
+            roster.apply(&line.parse().unwrap());
+        }
+    }
+
+    #[test]
+    fn presence_follows_events() {
+        let mut roster = Roster::default();
+        apply_all(
+            &mut roster,
+            &[
+                "u alice bob",
+                "c carol",
+                "r bob robert",
+                "d alice",
+                "m dave hi",
+            ],
+        );
+
+        assert_eq!(
+            roster.users().collect::<Vec<_>>(),
+            vec!["carol", "dave", "robert"]
+        );
+        assert!(!roster.contains("bob"));
+
+        roster.clear();
+        assert!(roster.is_empty());
+    }
+
+    #[test]
+    fn rooms_track_their_members() {
+        let mut server = Roster::default();
+        let mut room = Roster::for_room("rust");
+        let lines = [
+            "u alice bob carol",
+            "ru rust alice",
+            "j rust bob",
+            "j random carol",
+            "rm rust dave hi",
+            "l rust alice",
+            "r bob robert",
+            "r carol caroline",
+        ];
+        for line in lines {
+            let command = line.parse().unwrap();
+            server.apply(&command);
+            room.apply(&command);
+        }
+
+        assert_eq!(room.users().collect::<Vec<_>>(), vec!["dave", "robert"]);
+        assert_eq!(
+            server.users().collect::<Vec<_>>(),
+            vec!["alice", "caroline", "dave", "robert"]
+        );
+    }
+
+    #[test]
+    fn user_list_replaces_roster() {
+        let mut roster = Roster::default();
+        apply_all(&mut roster, &["c alice", "c bob", "u carol"]);
+
+        assert_eq!(roster.len(), 1);
+        assert!(roster.contains("carol"));
+    }
+}
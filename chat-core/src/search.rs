@@ -0,0 +1,132 @@
+use crate::net::commands::Record;
+
+use chrono::{Local, NaiveDate, TimeZone};
+use std::time::SystemTime;
+
+/// What to look for in chat history. Empty parts match everything.
+#[derive(Default, Clone, Debug, PartialEq)]
+pub struct Query {
+    pub text: String,
+    pub sender: String,
+    pub from: Option<SystemTime>,
+    pub until: Option<SystemTime>,
+}
+
+impl Query {
+    pub fn is_empty(&self) -> bool {
+        self.text.is_empty()
+            && self.sender.is_empty()
+            && self.from.is_none()
+            && self.until.is_none()
+    }
+
+    /// Indices of the entries matching the query, ignoring case.
+    pub fn search(&self, entries: &[Record]) -> Vec<usize> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let text = self.text.to_lowercase();
+        let sender = self.sender.to_lowercase();
+
+        entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                self.from.is_none_or(|from| entry.when() >= from)
+                    && self.until.is_none_or(|until| entry.when() < until)
+                    && entry.command.sender().to_lowercase().contains(&sender)
+                    && entry.command.text().to_lowercase().contains(&text)
+            })
+            .map(|(i, _)| i)
+            .collect()
+    }
+}
+
+fn start_of(date: NaiveDate) -> Option<SystemTime> {
+    let start = Local
+        .from_local_datetime(&date.and_hms_opt(0, 0, 0)?)
+        .earliest()?;
+
+    Some(start.into())
+}
+
+fn naive_date(date: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d").ok()
+}
+
+/// Start of a local `YYYY-MM-DD` date.
+pub fn parse_date(date: &str) -> Option<SystemTime> {
+    start_of(naive_date(date)?)
+}
+
+/// End of a local `YYYY-MM-DD` date, so ranges include the whole day.
+pub fn parse_end_date(date: &str) -> Option<SystemTime> {
+    start_of(naive_date(date)?.succ_opt()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::net::commands::ChatCommands;
+
+    use std::time::Duration;
+
+    fn entry(date: &str, sender: &str, message: &str) -> Record {
+        Record {
+            time: parse_date(date).unwrap() + Duration::from_secs(12 * 60 * 60),
+            ..Record::now(ChatCommands::Message {
+                sender: sender.to_string(),
+                message: message.to_string(),
+            })
+        }
+    }
+
+    #[test]
+    fn search_filters_every_part() {
+        let entries = vec![
+            entry("2022-01-01", "alice", "Hello world"),
+            entry("2022-01-02", "bob", "hello alice"),
+            entry("2022-01-03", "alice", "goodbye"),
+            Record {
+                server_time: parse_date("2022-01-04"),
+                ..Record::now(ChatCommands::UserConnected {
+                    name: "carol".to_string(),
+                })
+            },
+        ];
+
+        let query = |text: &str, sender: &str, from: &str, until: &str| Query {
+            text: text.to_string(),
+            sender: sender.to_string(),
+            from: parse_date(from),
+            until: parse_end_date(until),
+        };
+
+        assert_eq!(query("", "", "", "").search(&entries), Vec::<usize>::new());
+        assert_eq!(query("HELLO", "", "", "").search(&entries), vec![0, 1]);
+        assert_eq!(query("hello", "Alice", "", "").search(&entries), vec![0]);
+        assert_eq!(query("", "alice", "", "").search(&entries), vec![0, 2]);
+        assert_eq!(query("connected", "", "", "").search(&entries), vec![3]);
+        assert_eq!(
+            query("", "", "2022-01-02", "2022-01-03").search(&entries),
+            vec![1, 2]
+        );
+    }
+
+    #[test]
+    fn dates_are_validated() {
+        assert!(parse_date("2022-02-30").is_none());
+        assert!(parse_date("yesterday").is_none());
+
+        let start = parse_date(" 2022-03-01 ").unwrap();
+        assert_eq!(
+            parse_end_date("2022-03-01")
+                .unwrap()
+                .duration_since(start)
+                .ok(),
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+    }
+}
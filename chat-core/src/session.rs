@@ -0,0 +1,1138 @@
+//! A connection to one server and everything said on it, kept up to date
+//! by the network task without depending on how it is shown.
+
+use crate::{
+    complete::{Completion, Nicks},
+    history::{History, Retention},
+    mention,
+    net::{
+        client::{NetworkTask, Waker},
+        commands::*,
+        connection::ConnectionData,
+        error::NetError,
+    },
+    notify::{Notifier, NotifySettings},
+    outbox::Outbox,
+    roster::Roster,
+    search::Query,
+    slash::{self, Command, Input},
+};
+
+use std::{
+    collections::BTreeMap,
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+use tokio::sync::mpsc::{
+    self,
+    error::{TryRecvError, TrySendError},
+};
+
+/// Messages shown together, along with their history and the reply being
+/// written.
+pub struct Chat {
+    /// What the history is called, which is unique to the chat.
+    name: String,
+    /// The newest messages, with older ones only in the history.
+    messages: Vec<Record>,
+    /// How many older messages are only in the history.
+    paged_out: usize,
+    /// How many of the oldest messages were cleared, so aren't read back.
+    cleared: usize,
+    /// Most messages to hold before paging out the oldest, which is raised
+    /// while reading back older ones.
+    limit: usize,
+    in_memory: usize,
+    history: Option<History>,
+    /// The reply being written.
+    pub message: String,
+    /// Messages received since the chat was last shown.
+    unread: usize,
+    /// How many of the unread messages mention us.
+    mentions: usize,
+    /// Messages there were when the chat was last shown, counting those
+    /// paged out.
+    read: usize,
+    /// Where the unread messages started when the chat was shown again.
+    divider: Option<usize>,
+}
+
+impl Chat {
+    /// Opens and loads the history called `name`.
+    pub fn open(name: &str, retention: &Retention) -> Self {
+        Self::load(History::open(name), name, retention)
+    }
+
+    /// Opens and loads the history called `name` kept in `dir`.
+    pub fn open_in(dir: &Path, name: &str, retention: &Retention) -> Self {
+        Self::load(History::open_in(dir, name), name, retention)
+    }
+
+    /// A chat without history, so nothing in it is kept.
+    pub fn unsaved(name: &str, retention: &Retention) -> Self {
+        Self::new(None, Vec::new(), name, retention)
+    }
+
+    fn load(history: io::Result<History>, name: &str, retention: &Retention) -> Self {
+        // Chat still works without history, it just isn't kept
+        match history {
+            Ok(mut history) => {
+                let messages = history.load(retention).unwrap_or_else(|e| {
+                    tracing::warn!("Couldn't load history: {}", e);
+                    Vec::new()
+                });
+
+                Self::new(Some(history), messages, name, retention)
+            }
+            Err(e) => {
+                tracing::warn!("Couldn't open history: {}", e);
+                Self::unsaved(name, retention)
+            }
+        }
+    }
+
+    fn new(
+        history: Option<History>,
+        mut messages: Vec<Record>,
+        name: &str,
+        retention: &Retention,
+    ) -> Self {
+        let read = messages.len();
+        let paged_out = read.saturating_sub(retention.in_memory);
+        messages.drain(..paged_out);
+
+        Self {
+            name: name.to_string(),
+            messages,
+            paged_out,
+            cleared: 0,
+            limit: retention.in_memory,
+            in_memory: retention.in_memory,
+            history,
+            message: String::new(),
+            unread: 0,
+            mentions: 0,
+            read,
+            divider: None,
+        }
+    }
+
+    pub fn push(&mut self, record: Record) {
+        if let Some(history) = &mut self.history {
+            if let Err(e) = history.append(&record) {
+                tracing::warn!("Couldn't write history: {}", e);
+            }
+        }
+
+        self.messages.push(record);
+        self.page_out();
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn messages(&self) -> &[Record] {
+        &self.messages
+    }
+
+    /// How many older messages are only in the history, so the first in
+    /// `messages` is this many after the oldest.
+    pub fn paged_out(&self) -> usize {
+        self.paged_out
+    }
+
+    /// Messages received since the chat was last read.
+    pub fn unread(&self) -> usize {
+        self.unread
+    }
+
+    /// How many of the unread messages mention us.
+    pub fn mentions(&self) -> usize {
+        self.mentions
+    }
+
+    /// Where the unread messages started when the chat was last returned to,
+    /// counted from the oldest message in the history.
+    pub fn divider(&self) -> Option<usize> {
+        self.divider
+    }
+
+    /// Adds a message from someone else, which counts as unread until the
+    /// chat is shown.
+    fn push_unread(&mut self, record: Record, mention: bool) {
+        self.unread += 1;
+        if mention {
+            self.mentions += 1;
+        }
+
+        self.push(record);
+    }
+
+    /// Marks everything as read, leaving a divider before the first message
+    /// that wasn't if the chat is being `returned` to.
+    pub fn mark_read(&mut self, returned: bool) {
+        if returned {
+            self.divider = (self.unread > 0).then_some(self.read);
+        }
+
+        self.unread = 0;
+        self.mentions = 0;
+        self.read = self.paged_out + self.messages.len();
+    }
+
+    /// The message `row` messages after the oldest in the history, if it is
+    /// in memory.
+    pub fn message(&self, row: usize) -> Option<&Record> {
+        self.messages.get(row.checked_sub(self.paged_out)?)
+    }
+
+    /// Drops the oldest messages beyond the limit, which can be read back
+    /// from history.
+    fn page_out(&mut self) {
+        // Without history there is nowhere to read them back from
+        if self.history.is_none() || self.messages.len() <= self.limit {
+            return;
+        }
+
+        let rows = self.messages.len() - self.limit;
+        self.messages.drain(..rows);
+        self.paged_out += rows;
+    }
+
+    /// Reads back up to `rows` of the messages before those in memory,
+    /// returning how many there were.
+    pub fn read_back(&mut self, rows: usize) -> usize {
+        let history = match &self.history {
+            Some(history) if self.paged_out > self.cleared => history,
+            _ => return 0,
+        };
+
+        let start = self.paged_out.saturating_sub(rows).max(self.cleared);
+        let older = history.read(start..self.paged_out).unwrap_or_else(|e| {
+            tracing::warn!("Couldn't read history: {}", e);
+            Vec::new()
+        });
+        let read = older.len();
+
+        self.messages.splice(0..0, older);
+        self.paged_out = start;
+        self.limit = self.limit.max(self.messages.len());
+
+        read
+    }
+
+    /// The messages in `rows`, counted from the oldest in the history,
+    /// reading back any paged out. Cleared messages are left out.
+    pub fn rows(&self, rows: Range<usize>) -> Vec<Record> {
+        let start = rows.start.max(self.cleared);
+        let mut records = Vec::new();
+
+        if let Some(history) = self.history.as_ref().filter(|_| start < self.paged_out) {
+            records = history
+                .read(start..rows.end.min(self.paged_out))
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Couldn't read history: {}", e);
+                    Vec::new()
+                });
+        }
+
+        let from = start.saturating_sub(self.paged_out);
+        let to = rows
+            .end
+            .saturating_sub(self.paged_out)
+            .min(self.messages.len());
+        if from < to {
+            records.extend_from_slice(&self.messages[from..to]);
+        }
+
+        records
+    }
+
+    /// Rows of the messages matching `query`, counted from the oldest in the
+    /// history, including those paged out but not those cleared.
+    pub fn search(&self, query: &Query) -> Vec<usize> {
+        let mut rows = Vec::new();
+
+        // Paged out messages are read back a page at a time
+        let page = self.in_memory.max(1);
+        for start in (self.cleared..self.paged_out).step_by(page) {
+            let records = self.rows(start..(start + page).min(self.paged_out));
+            rows.extend(query.search(&records).into_iter().map(|row| start + row));
+        }
+
+        let paged_out = self.paged_out;
+        rows.extend(
+            query
+                .search(&self.messages)
+                .into_iter()
+                .map(|row| paged_out + row),
+        );
+        rows
+    }
+
+    /// Hides every message so far until the chat is opened again, though
+    /// they stay in the history.
+    pub fn clear(&mut self) {
+        self.paged_out += self.messages.len();
+        self.messages.clear();
+        self.cleared = self.paged_out;
+        self.limit = self.in_memory;
+        self.divider = None;
+    }
+
+    /// Takes in everything said in `other`, in the order it was received,
+    /// and deletes its history.
+    pub fn merge(&mut self, other: Chat) {
+        let mut records = self.records();
+        records.extend(other.records());
+        records.sort_by_key(|record| record.time);
+
+        if let Some(history) = other.history {
+            if let Err(e) = history.remove() {
+                tracing::warn!("Couldn't remove merged history: {}", e);
+            }
+        }
+        if let Some(history) = &mut self.history {
+            if let Err(e) = history.rewrite(&records) {
+                tracing::warn!("Couldn't write history: {}", e);
+            }
+        }
+
+        let total = records.len();
+        self.paged_out = match self.history {
+            Some(_) => total.saturating_sub(self.in_memory),
+            None => 0,
+        };
+        self.messages = records.split_off(self.paged_out);
+        self.cleared = 0;
+        self.limit = self.in_memory;
+
+        self.unread += other.unread;
+        self.mentions += other.mentions;
+        self.read = total - self.unread.min(total);
+        self.divider = None;
+    }
+
+    /// Every message, reading back those paged out.
+    fn records(&self) -> Vec<Record> {
+        let mut records = match &self.history {
+            Some(history) => history.read(0..self.paged_out).unwrap_or_else(|e| {
+                tracing::warn!("Couldn't read history: {}", e);
+                Vec::new()
+            }),
+            None => Vec::new(),
+        };
+
+        records.extend(self.messages.iter().cloned());
+        records
+    }
+
+    /// Pages out again anything read back, once scrolled away from.
+    pub fn forget_read_back(&mut self) {
+        if self.limit > self.in_memory {
+            self.limit = self.in_memory;
+            self.page_out();
+        }
+    }
+}
+
+/// Direct messages with one other user.
+pub struct Conversation {
+    pub peer: String,
+    pub chat: Chat,
+}
+
+/// A room we've joined on the server.
+pub struct Room {
+    pub chat: Chat,
+    pub members: Roster,
+}
+
+/// Which of a session's chats is shown.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum View {
+    /// Messages to everyone on the server.
+    #[default]
+    Server,
+    Room(String),
+    Conversation(usize),
+}
+
+/// Where a session keeps its history and outbox.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub enum Persistence {
+    /// In the app's own directory.
+    #[default]
+    Default,
+    /// In another directory, like one given on the command line.
+    In(PathBuf),
+    /// Nowhere, so everything is forgotten with the session.
+    Off,
+}
+
+impl Persistence {
+    fn open_chat(&self, name: &str, retention: &Retention) -> Chat {
+        match self {
+            Self::Default => Chat::open(name, retention),
+            Self::In(dir) => Chat::open_in(&dir.join("history"), name, retention),
+            Self::Off => Chat::unsaved(name, retention),
+        }
+    }
+
+    fn open_outbox(&self, server: &str) -> io::Result<Outbox> {
+        match self {
+            Self::Default => Outbox::open(server),
+            Self::In(dir) => Outbox::open_in(&dir.join("outbox"), server),
+            Self::Off => Ok(Outbox::default()),
+        }
+    }
+}
+
+/// Room names are single words on the wire.
+pub fn room_name(name: &str) -> String {
+    name.trim_start_matches('#')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+/// How many requests can wait for the network task before sending is refused.
+const SEND_QUEUE: usize = 100;
+
+/// Everything to do with one server, with a network task keeping it
+/// connected.
+pub struct Session {
+    /// Messages to everyone on the server.
+    pub chat: Chat,
+    pub roster: Roster,
+    /// Everyone seen on the server, for completing their names.
+    pub nicks: Nicks,
+    /// The name being completed in the composer.
+    pub completion: Option<Completion>,
+    pub rooms: BTreeMap<String, Room>,
+    /// Every room on the server, as of the last time we asked.
+    pub available_rooms: Vec<String>,
+    pub conversations: Vec<Conversation>,
+    pub view: View,
+    /// Messages written while disconnected.
+    outbox: Outbox,
+
+    send: mpsc::Sender<ClientRequest>,
+    recv: mpsc::Receiver<ClientCommands>,
+    /// Disconnects from the server when the session is dropped or replaced.
+    task: NetworkTask,
+    waker: Waker,
+    persistence: Persistence,
+
+    pub connect_state: ConnectState,
+    pub error: Option<NetError>,
+    /// Round-trip time of the latest ping while connected.
+    pub latency: Option<Duration>,
+    /// Problem with the last thing the user did.
+    pub notice: Option<String>,
+    /// Why the command last typed couldn't be run, shown by the composer.
+    pub command_error: Option<String>,
+
+    pub connection: ConnectionData,
+}
+
+impl Session {
+    /// Starts connecting to the server in `connection`, calling `waker`
+    /// whenever there is something new to show.
+    ///
+    /// Must be called from within a tokio runtime.
+    pub fn new(connection: ConnectionData, waker: Waker) -> Self {
+        Self::new_with(Persistence::Default, connection, waker)
+    }
+
+    /// Like [`Session::new`], but keeps history and the outbox as
+    /// `persistence` says.
+    pub fn new_with(persistence: Persistence, connection: ConnectionData, waker: Waker) -> Self {
+        let (session_send, client_recv) = mpsc::channel::<ClientRequest>(SEND_QUEUE);
+        let (client_send, session_recv) = mpsc::channel::<ClientCommands>(100);
+
+        let task = NetworkTask::spawn(client_send, client_recv, waker.clone(), connection.clone());
+
+        Self {
+            chat: persistence.open_chat(connection.server(), connection.retention()),
+            roster: Roster::default(),
+            nicks: Nicks::default(),
+            completion: None,
+            rooms: BTreeMap::new(),
+            available_rooms: Vec::new(),
+            conversations: Vec::new(),
+            view: View::Server,
+            outbox: persistence
+                .open_outbox(connection.server())
+                .unwrap_or_else(|e| {
+                    tracing::warn!("Couldn't open outbox: {}", e);
+                    Outbox::default()
+                }),
+            send: session_send,
+            recv: session_recv,
+            task,
+            waker,
+            persistence,
+            connect_state: ConnectState::Loading,
+            error: None,
+            latency: None,
+            notice: None,
+            command_error: None,
+            connection,
+        }
+    }
+
+    /// Starts over on another server, or the same one differently, as a new
+    /// session would.
+    pub fn restart(&mut self, connection: ConnectionData) {
+        // Each history can only be open in one place, so the old session's
+        // have to go first
+        self.chat = Chat::unsaved(self.chat.name(), connection.retention());
+        self.rooms.clear();
+        self.conversations.clear();
+
+        *self = Self::new_with(self.persistence.clone(), connection, self.waker.clone());
+    }
+
+    /// Changes the server's details as `edit` says, starting from the
+    /// current ones so anything it leaves alone is kept. Another address or
+    /// TLS setting is another server, so the session starts over there
+    /// without the pinned certificate.
+    pub fn edit_connection(&mut self, edit: impl FnOnce(&mut ConnectionData)) {
+        let mut connection = self.connection.clone();
+        edit(&mut connection);
+
+        if connection.server() != self.connection.server()
+            || connection.tls() != self.connection.tls()
+        {
+            connection.set_fingerprint(None);
+            self.restart(connection);
+        } else {
+            if connection.name() != self.connection.name() {
+                self.change_name(connection.name());
+            }
+            self.connection = connection;
+        }
+    }
+
+    pub fn reconnect(&mut self) {
+        let (session_send, client_recv) = mpsc::channel::<ClientRequest>(SEND_QUEUE);
+        let (client_send, session_recv) = mpsc::channel::<ClientCommands>(100);
+
+        // Replacing the task disconnects the old one, or stops it waiting to
+        // reconnect, before starting over
+        self.task = NetworkTask::spawn(
+            client_send,
+            client_recv,
+            self.waker.clone(),
+            self.connection.clone(),
+        );
+
+        self.send = session_send;
+        self.recv = session_recv;
+        self.connect_state = ConnectState::Loading;
+        self.error = None;
+        self.clear_presence();
+    }
+
+    pub fn stop_reconnecting(&mut self) {
+        self.task.stop();
+        self.connect_state = ConnectState::Disconnect;
+    }
+
+    /// Forgets who is online, which the server tells us again when we
+    /// connect.
+    fn clear_presence(&mut self) {
+        self.roster.clear();
+        for room in self.rooms.values_mut() {
+            room.members.clear();
+        }
+    }
+
+    /// Finds the conversation with `peer`, starting one if there isn't one
+    /// yet.
+    pub fn conversation(&mut self, peer: &str) -> usize {
+        match self.conversations.iter().position(|c| c.peer == peer) {
+            Some(i) => i,
+            None => {
+                let name = format!("{} dm {}", self.connection.server(), peer);
+                let chat = self
+                    .persistence
+                    .open_chat(&name, self.connection.retention());
+
+                self.conversations.push(Conversation {
+                    peer: peer.to_string(),
+                    chat,
+                });
+                self.conversations.len() - 1
+            }
+        }
+    }
+
+    /// Moves the conversation with `oldname` over to their new name, with
+    /// its history, merging it into any already had with that name.
+    fn rename_conversation(&mut self, oldname: &str, newname: &str) {
+        let i = match self.conversations.iter().position(|c| c.peer == oldname) {
+            Some(i) => i,
+            None => return,
+        };
+
+        let shown = self.view == View::Conversation(i);
+        let old = self.conversations.remove(i);
+        self.view = match self.view {
+            View::Conversation(current) if current > i => View::Conversation(current - 1),
+            ref view => view.clone(),
+        };
+
+        let j = self.conversation(newname);
+        self.conversations[j].chat.merge(old.chat);
+        if shown {
+            self.view = View::Conversation(j);
+        }
+    }
+
+    pub fn close_conversation(&mut self, i: usize) {
+        self.conversations.remove(i);
+
+        self.view = match self.view {
+            View::Conversation(current) if current == i => View::Server,
+            View::Conversation(current) if current > i => View::Conversation(current - 1),
+            _ => return,
+        };
+    }
+
+    pub fn join_room(&mut self, name: &str) {
+        if !self.rooms.contains_key(name) {
+            let history = format!("{} room {}", self.connection.server(), name);
+            let chat = self
+                .persistence
+                .open_chat(&history, self.connection.retention());
+
+            self.rooms.insert(
+                name.to_string(),
+                Room {
+                    chat,
+                    members: Roster::for_room(name),
+                },
+            );
+            // Rooms are joined again on connecting anyway
+            let _ = self.send(ClientRequest::Join {
+                room: name.to_string(),
+            });
+        }
+    }
+
+    pub fn leave_room(&mut self, name: &str) {
+        self.rooms.remove(name);
+        let _ = self.send(ClientRequest::Leave {
+            room: name.to_string(),
+        });
+
+        if matches!(&self.view, View::Room(room) if room == name) {
+            self.view = View::Server;
+        }
+    }
+
+    /// The chat currently shown.
+    pub fn current_chat(&mut self) -> &mut Chat {
+        match &self.view {
+            View::Server => &mut self.chat,
+            View::Room(room) => &mut self.rooms.get_mut(room).unwrap().chat,
+            View::Conversation(i) => &mut self.conversations[*i].chat,
+        }
+    }
+
+    /// Adds `name` to the composer, addressing them if nothing has been typed
+    /// yet.
+    pub fn mention(&mut self, name: &str) {
+        let message = &mut self.current_chat().message;
+
+        if message.trim().is_empty() {
+            *message = format!("{}: ", name);
+        } else {
+            if !message.ends_with(' ') {
+                message.push(' ');
+            }
+            *message += name;
+            message.push(' ');
+        }
+    }
+
+    /// Completes what is written in the current chat as Tab does, with the
+    /// only command it could be, or else the next name matching the word
+    /// before `cursor`, counted in characters. Returns where the cursor goes
+    /// after, if anything was completed.
+    pub fn complete(&mut self, cursor: usize) -> Option<usize> {
+        let text = self.current_chat().message.clone();
+
+        if let [spec] = slash::matching(&text)[..] {
+            let message = format!("/{} ", spec.name);
+            let cursor = message.chars().count();
+            self.current_chat().message = message;
+            return Some(cursor);
+        }
+
+        if !self
+            .completion
+            .as_ref()
+            .is_some_and(|completion| completion.is_current(&text))
+        {
+            self.completion = Completion::start(&text, cursor, &self.nicks, self.connection.name());
+        }
+
+        let mut completion = self.completion.take()?;
+        let cursor = completion.next(&mut self.current_chat().message);
+        self.completion = Some(completion);
+        Some(cursor)
+    }
+
+    /// Completes the name being completed with candidate `i` instead,
+    /// returning where the cursor goes after.
+    pub fn select_completion(&mut self, i: usize) -> Option<usize> {
+        let mut completion = self.completion.take()?;
+        let cursor = completion.select(i, &mut self.current_chat().message);
+        self.completion = Some(completion);
+        Some(cursor)
+    }
+
+    pub fn change_name(&mut self, name: &str) {
+        // Otherwise the new name is used when connecting again
+        let _ = self.send(ClientRequest::Rename {
+            name: name.to_string(),
+        });
+
+        self.connection.set_name(name);
+    }
+
+    /// Sends what is written in the current chat, or runs it if it's a
+    /// command. Returns the commands only the front end can run.
+    pub fn send_message(&mut self) -> Option<Command> {
+        let message = std::mem::take(&mut self.current_chat().message);
+        self.command_error = None;
+
+        let request = match slash::parse(&message) {
+            Ok(Input::Text(text)) => self.message_request(text.to_string()),
+            Ok(Input::Raw(line)) => ClientRequest::Raw(line.to_string()),
+            Ok(Input::Command(command)) => return self.run(command, &message),
+
+            // Leave the command to be fixed
+            Err(e) => {
+                self.command_error = Some(e.to_string());
+                self.current_chat().message = message;
+                return None;
+            }
+        };
+
+        self.submit(request, &message);
+        None
+    }
+
+    /// A message to whoever the current chat is with.
+    fn message_request(&self, message: String) -> ClientRequest {
+        match &self.view {
+            View::Server => ClientRequest::Message { message },
+            View::Room(room) => ClientRequest::RoomMessage {
+                room: room.clone(),
+                message,
+            },
+            View::Conversation(i) => ClientRequest::PrivateMessage {
+                target: self.conversations[*i].peer.clone(),
+                message,
+            },
+        }
+    }
+
+    /// Sends `request` if connected, otherwise keeps it in the outbox. While
+    /// the outbox still has anything in it, `request` waits behind that.
+    ///
+    /// `line` is what was typed for it, given back to be sent again if the
+    /// send buffer is full.
+    fn submit(&mut self, request: ClientRequest, line: &str) {
+        if !self.outbox.pending().is_empty() {
+            self.outbox.push(request);
+            self.save_outbox();
+
+            if self.connect_state == ConnectState::Connected {
+                self.flush_outbox();
+            }
+            return;
+        }
+
+        let request = if self.connect_state == ConnectState::Connected {
+            match self.send(request.clone()) {
+                Ok(()) => {
+                    self.sent(&request);
+                    self.notice = None;
+                    return;
+                }
+                Err(TrySendError::Full(_)) => {
+                    self.current_chat().message = line.to_string();
+                    return;
+                }
+
+                // The connection has only just gone
+                Err(TrySendError::Closed(request)) => request,
+            }
+        } else {
+            request
+        };
+
+        self.outbox.push(request);
+        self.save_outbox();
+    }
+
+    /// Runs a command typed into the current chat as `line`, returning it if
+    /// it's up to the front end.
+    fn run(&mut self, command: Command, line: &str) -> Option<Command> {
+        match command {
+            Command::Clear => self.current_chat().clear(),
+            Command::Reconnect => self.reconnect(),
+            Command::Nick(name) => self.change_name(&name),
+
+            Command::Close => match self.view.clone() {
+                View::Server => return Some(Command::Close),
+                View::Room(name) => self.leave_room(&name),
+                View::Conversation(i) => self.close_conversation(i),
+            },
+
+            Command::Join(room) => {
+                let room = room_name(&room);
+                self.join_room(&room);
+                self.view = View::Room(room);
+            }
+
+            Command::Me(action) => {
+                let request = self.message_request(format!("{}{}", slash::ACTION, action));
+                self.submit(request, line);
+            }
+
+            Command::Msg { target, message } => {
+                self.view = View::Conversation(self.conversation(&target));
+                self.submit(ClientRequest::PrivateMessage { target, message }, line);
+            }
+
+            Command::Ignore(None) => {
+                self.notice = Some(match self.connection.ignored() {
+                    [] => "Not ignoring anyone".to_string(),
+                    ignored => format!("Ignoring {}", ignored.join(", ")),
+                });
+            }
+            Command::Ignore(Some(name)) => {
+                if self.connection.ignore(&name) {
+                    self.notice = Some(format!("Ignoring {}", name));
+                } else {
+                    self.command_error = Some(format!("Already ignoring {}", name));
+                }
+            }
+            Command::Unignore(name) => {
+                if self.connection.unignore(&name) {
+                    self.notice = Some(format!("No longer ignoring {}", name));
+                } else {
+                    self.command_error = Some(format!("Not ignoring {}", name));
+                }
+            }
+
+            Command::Help(_) => return Some(command),
+        }
+
+        None
+    }
+
+    /// Keeps our own copy of anything the server won't echo back.
+    fn sent(&mut self, request: &ClientRequest) {
+        if let ClientRequest::PrivateMessage { target, message } = request {
+            let record = Record::now(ChatCommands::PrivateMessage {
+                sender: self.connection.name().to_string(),
+                message: message.clone(),
+            });
+
+            let i = self.conversation(target);
+            self.conversations[i].chat.push(record);
+        }
+    }
+
+    /// Queues `request` for the network task without waiting.
+    pub fn send(&mut self, request: ClientRequest) -> Result<(), TrySendError<ClientRequest>> {
+        let result = self.send.try_send(request);
+
+        if let Err(TrySendError::Full(_)) = result {
+            self.notice = Some("Send buffer full, try again in a moment".to_string());
+        }
+
+        result
+    }
+
+    /// Sends everything in the outbox, keeping whatever can't be sent yet.
+    fn flush_outbox(&mut self) {
+        let mut pending = self.outbox.take().into_iter();
+
+        while let Some(request) = pending.next() {
+            if let Err(e) = self.send(request.clone()) {
+                self.outbox.push(e.into_inner());
+                for request in pending.by_ref() {
+                    self.outbox.push(request);
+                }
+            } else {
+                self.sent(&request);
+            }
+        }
+
+        self.save_outbox();
+    }
+
+    fn save_outbox(&self) {
+        if let Err(e) = self.outbox.save() {
+            tracing::warn!("Couldn't save outbox: {}", e);
+        }
+    }
+
+    /// Messages waiting in the outbox for the chat shown, with their place in
+    /// it.
+    pub fn pending(&self) -> Vec<(usize, ClientRequest)> {
+        let shown = |request: &ClientRequest| match (request, &self.view) {
+            (ClientRequest::Message { .. } | ClientRequest::Raw(_), View::Server) => true,
+            (ClientRequest::RoomMessage { room, .. }, View::Room(shown)) => room == shown,
+            (ClientRequest::PrivateMessage { target, .. }, View::Conversation(i)) => {
+                *target == self.conversations[*i].peer
+            }
+            _ => false,
+        };
+
+        self.outbox
+            .pending()
+            .iter()
+            .enumerate()
+            .filter(|(_, request)| shown(request))
+            .map(|(i, request)| (i, request.clone()))
+            .collect()
+    }
+
+    /// Takes a message back out of the outbox, to write it again.
+    pub fn edit_pending(&mut self, i: usize) {
+        let text = request_text(&self.outbox.remove(i));
+        self.save_outbox();
+
+        self.current_chat().message = text;
+    }
+
+    pub fn cancel_pending(&mut self, i: usize) {
+        self.outbox.remove(i);
+        self.save_outbox();
+    }
+
+    /// Files a chat event under the chats it belongs to.
+    fn receive(&mut self, record: Record) {
+        self.roster.apply(&record.command);
+        self.nicks.apply(&record.command);
+
+        match &record.command {
+            // Only a snapshot of who's online, not worth keeping
+            ChatCommands::UserList { .. } => return,
+
+            ChatCommands::RoomList { rooms } => {
+                self.available_rooms = rooms.clone();
+                return;
+            }
+
+            ChatCommands::PrivateMessage { sender, .. } => {
+                let i = self.conversation(sender);
+                self.conversations[i].chat.push_unread(record, true);
+                return;
+            }
+
+            ChatCommands::RoomMessage { room, .. }
+            | ChatCommands::UserJoined { room, .. }
+            | ChatCommands::UserLeft { room, .. }
+            | ChatCommands::RoomUsers { room, .. } => {
+                // Anything for a room we've left was already on its way
+                if let Some(room) = self.rooms.get_mut(room) {
+                    room.members.apply(&record.command);
+
+                    match &record.command {
+                        ChatCommands::RoomUsers { .. } => (),
+                        ChatCommands::RoomMessage {
+                            sender, message, ..
+                        } if sender != self.connection.name() => {
+                            let mention = mention::mentions(message, self.connection.name());
+                            room.chat.push_unread(record, mention);
+                        }
+                        _ => room.chat.push(record),
+                    }
+                }
+                return;
+            }
+
+            _ => (),
+        }
+
+        // Let rooms and conversations know when their people come and go
+        for room in self.rooms.values_mut() {
+            let about_member = match &record.command {
+                ChatCommands::UserDisconnected { name } => room.members.contains(name),
+                ChatCommands::UserRenamed { oldname, .. } => room.members.contains(oldname),
+                _ => false,
+            };
+
+            room.members.apply(&record.command);
+            if about_member {
+                room.chat.push(record.clone());
+            }
+        }
+
+        if let ChatCommands::UserRenamed { oldname, newname } = &record.command {
+            self.rename_conversation(oldname, newname);
+        }
+
+        for conversation in &mut self.conversations {
+            match &record.command {
+                ChatCommands::UserConnected { name }
+                | ChatCommands::UserDisconnected { name }
+                | ChatCommands::UserRenamed { newname: name, .. }
+                    if *name == conversation.peer =>
+                {
+                    conversation.chat.push(record.clone());
+                }
+                _ => (),
+            }
+        }
+
+        match &record.command {
+            ChatCommands::Message { sender, message } if sender != self.connection.name() => {
+                let mention = mention::mentions(message, self.connection.name());
+                self.chat.push_unread(record, mention);
+            }
+            _ => self.chat.push(record),
+        }
+    }
+
+    /// Whether `command` is a message from someone being ignored.
+    fn ignores(&self, command: &ChatCommands) -> bool {
+        match command {
+            ChatCommands::Message { sender, .. }
+            | ChatCommands::PrivateMessage { sender, .. }
+            | ChatCommands::RoomMessage { sender, .. } => self.connection.is_ignored(sender),
+            _ => false,
+        }
+    }
+
+    /// Every one of the session's chats, with the view that shows it.
+    pub fn chats(&self) -> impl Iterator<Item = (View, &Chat)> {
+        std::iter::once((View::Server, &self.chat))
+            .chain(
+                self.rooms
+                    .iter()
+                    .map(|(name, room)| (View::Room(name.clone()), &room.chat)),
+            )
+            .chain(
+                self.conversations
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| (View::Conversation(i), &c.chat)),
+            )
+    }
+
+    /// Unread messages and mentions across all of the session's chats.
+    pub fn unread(&self) -> (usize, usize) {
+        self.chats().fold((0, 0), |(unread, mentions), (_, chat)| {
+            (unread + chat.unread, mentions + chat.mentions)
+        })
+    }
+
+    pub fn sync_messages(&mut self, notify: &NotifySettings, notifier: &mut dyn Notifier) {
+        if self.connect_state != ConnectState::Failed
+            || self.connect_state != ConnectState::Disconnect
+        {
+            loop {
+                match self.recv.try_recv() {
+                    Ok(ClientCommands::ChatCommand(record)) => {
+                        if self.ignores(&record.command) {
+                            continue;
+                        }
+
+                        if let Some(notification) =
+                            notify.notification(&self.connection, &record.command)
+                        {
+                            notifier.notify(notification);
+                        }
+
+                        self.receive(record);
+                    }
+                    Ok(ClientCommands::ConnectState(s)) => {
+                        // Each new session starts from the server's user lists
+                        self.clear_presence();
+
+                        if s == ConnectState::Connected {
+                            // The server forgets which rooms we were in when we leave
+                            let rooms = self.rooms.keys().cloned().collect::<Vec<_>>();
+                            for room in rooms {
+                                let _ = self.send(ClientRequest::Join { room });
+                            }
+
+                            self.flush_outbox();
+
+                            // Whatever went wrong before has been got past
+                            self.error = None;
+                        }
+
+                        self.connect_state = s;
+                        self.latency = None;
+                    }
+                    Ok(ClientCommands::Latency(latency)) => self.latency = Some(latency),
+                    Ok(ClientCommands::Error(e)) => self.error = Some(e),
+
+                    Err(TryRecvError::Disconnected) => {
+                        self.clear_presence();
+
+                        match self.connect_state {
+                            ConnectState::Connected => {
+                                self.connect_state = ConnectState::Disconnect
+                            }
+                            ConnectState::Loading | ConnectState::Reconnecting { .. } => {
+                                self.connect_state = ConnectState::Failed
+                            }
+                            _ => (),
+                        }
+
+                        break;
+                    }
+
+                    Err(TryRecvError::Empty) => break,
+                }
+            }
+        }
+
+        // Anything that didn't fit in the send buffer last time goes now
+        if self.connect_state == ConnectState::Connected && !self.outbox.pending().is_empty() {
+            self.flush_outbox();
+        }
+    }
+}
+
+/// What was written to make `request`.
+pub fn request_text(request: &ClientRequest) -> String {
+    match request {
+        ClientRequest::Message { message }
+        | ClientRequest::RoomMessage { message, .. }
+        | ClientRequest::PrivateMessage { message, .. } => message.clone(),
+        ClientRequest::Raw(line) => format!("/{}", line),
+        request => request.to_string(),
+    }
+}
+
+/// A message as a single line of text.
+pub fn record_line(record: &Record) -> String {
+    match &record.command {
+        ChatCommands::Message { sender, message }
+        | ChatCommands::PrivateMessage { sender, message }
+        | ChatCommands::RoomMessage {
+            sender, message, ..
+        } => match slash::action(message) {
+            Some(action) => format!("* {} {}", sender, action),
+            None => format!("{}: {}", sender, message),
+        },
+        c => c.text(),
+    }
+}
@@ -0,0 +1,281 @@
+//! Commands typed into the composer after a `/`.
+//!
+//! Commands the client knows are run by the session, anything else is passed
+//! through to the server as it was typed. A message can still start with a
+//! `/` by doubling it.
+
+use std::fmt;
+
+/// A command the client knows, as listed by `/help`.
+#[derive(Debug, PartialEq)]
+pub struct Spec {
+    pub name: &'static str,
+    /// What follows the name, with optional arguments in brackets.
+    pub args: &'static str,
+    pub about: &'static str,
+}
+
+impl Spec {
+    pub fn usage(&self) -> String {
+        if self.args.is_empty() {
+            format!("/{}", self.name)
+        } else {
+            format!("/{} {}", self.name, self.args)
+        }
+    }
+}
+
+/// Every command the client knows, by name.
+pub const COMMANDS: &[Spec] = &[
+    Spec {
+        name: "clear",
+        args: "",
+        about: "Clear the messages shown in this chat",
+    },
+    Spec {
+        name: "close",
+        args: "",
+        about: "Leave this room, close this conversation or close the server",
+    },
+    Spec {
+        name: "help",
+        args: "[command]",
+        about: "List the commands, or explain one",
+    },
+    Spec {
+        name: "ignore",
+        args: "[name]",
+        about: "Hide messages from someone, or list who is hidden",
+    },
+    Spec {
+        name: "join",
+        args: "<room>",
+        about: "Join a room and show it",
+    },
+    Spec {
+        name: "me",
+        args: "<action>",
+        about: "Describe what you are doing",
+    },
+    Spec {
+        name: "msg",
+        args: "<name> <message>",
+        about: "Send someone a direct message",
+    },
+    Spec {
+        name: "nick",
+        args: "<name>",
+        about: "Change your name on this server",
+    },
+    Spec {
+        name: "reconnect",
+        args: "",
+        about: "Connect to the server again",
+    },
+    Spec {
+        name: "unignore",
+        args: "<name>",
+        about: "Show messages from someone again",
+    },
+];
+
+/// Messages starting with this describe what the sender is doing.
+pub const ACTION: &str = "/me ";
+
+/// A command read from the composer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Clear,
+    Close,
+    Help(Option<&'static Spec>),
+    /// Ignores someone, or lists who is ignored without a name.
+    Ignore(Option<String>),
+    Join(String),
+    Me(String),
+    Msg {
+        target: String,
+        message: String,
+    },
+    Nick(String),
+    Reconnect,
+    Unignore(String),
+}
+
+/// What was typed into the composer.
+#[derive(Debug, PartialEq)]
+pub enum Input<'a> {
+    /// A message to whoever the chat is with.
+    Text(&'a str),
+    Command(Command),
+    /// A command the client doesn't know, without its `/`.
+    Raw(&'a str),
+}
+
+/// Why a command couldn't be read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandError {
+    /// The command's arguments are missing or there are too many.
+    Usage(&'static Spec),
+    /// `/help` was asked about a command that doesn't exist.
+    NoHelp(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Usage(spec) => write!(f, "Usage: {}", spec.usage()),
+            Self::NoHelp(name) => write!(f, "There's no /{} command", name),
+        }
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+pub fn find(name: &str) -> Option<&'static Spec> {
+    COMMANDS.iter().find(|spec| spec.name == name)
+}
+
+/// Commands whose names start with what is typed after the `/`, while the
+/// name is still being typed.
+pub fn matching(line: &str) -> Vec<&'static Spec> {
+    match line.strip_prefix('/') {
+        Some(name) if !name.starts_with('/') && !name.contains(' ') => COMMANDS
+            .iter()
+            .filter(|spec| spec.name.starts_with(name))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The action described by a message sent with `/me`.
+pub fn action(message: &str) -> Option<&str> {
+    message.strip_prefix(ACTION)
+}
+
+/// Reads a line from the composer.
+pub fn parse(line: &str) -> Result<Input<'_>, CommandError> {
+    let command = match line.strip_prefix('/') {
+        Some(command) if !command.starts_with('/') => command,
+        Some(escaped) => return Ok(Input::Text(escaped)),
+        None => return Ok(Input::Text(line)),
+    };
+
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let spec = match find(name) {
+        Some(spec) => spec,
+        // Without a name there's no command to pass on
+        None if name.is_empty() => return Ok(Input::Text(line)),
+        None => return Ok(Input::Raw(command)),
+    };
+
+    let usage = || CommandError::Usage(spec);
+    let args = args.trim();
+    let mut words = args.split_whitespace();
+    let word = words.next().map(str::to_string);
+    let one_word = words.next().is_none();
+
+    let command = match (spec.name, word) {
+        ("clear", None) => Command::Clear,
+        ("close", None) => Command::Close,
+        ("reconnect", None) => Command::Reconnect,
+
+        ("help", None) => Command::Help(None),
+        ("help", Some(topic)) if one_word => {
+            let topic = topic.trim_start_matches('/');
+            Command::Help(Some(
+                find(topic).ok_or_else(|| CommandError::NoHelp(topic.to_string()))?,
+            ))
+        }
+
+        ("ignore", name) if one_word => Command::Ignore(name),
+        ("unignore", Some(name)) if one_word => Command::Unignore(name),
+        // A room needs a name besides the #
+        ("join", Some(room)) if one_word && !room.trim_start_matches('#').is_empty() => {
+            Command::Join(room)
+        }
+        ("nick", Some(name)) if one_word => Command::Nick(name),
+
+        ("me", Some(_)) => Command::Me(args.to_string()),
+        ("msg", Some(target)) => match args[target.len()..].trim() {
+            "" => return Err(usage()),
+            message => Command::Msg {
+                target,
+                message: message.to_string(),
+            },
+        },
+
+        _ => return Err(usage()),
+    };
+
+    Ok(Input::Command(command))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(line: &str) -> Command {
+        match parse(line) {
+            Ok(Input::Command(command)) => command,
+            other => panic!("{:?} isn't a command: {:?}", line, other),
+        }
+    }
+
+    #[test]
+    fn commands_are_read() {
+        assert_eq!(command("/nick bob"), Command::Nick("bob".to_string()));
+        assert_eq!(
+            command("/msg  carol  see you  soon"),
+            Command::Msg {
+                target: "carol".to_string(),
+                message: "see you  soon".to_string(),
+            }
+        );
+        assert_eq!(command("/me waves"), Command::Me("waves".to_string()));
+        assert_eq!(command("/clear"), Command::Clear);
+        assert_eq!(command("/ignore"), Command::Ignore(None));
+        assert_eq!(command("/help /msg"), Command::Help(find("msg")));
+    }
+
+    #[test]
+    fn everything_else_is_passed_on() {
+        assert_eq!(parse("hello"), Ok(Input::Text("hello")));
+        assert_eq!(parse("//shrug"), Ok(Input::Text("/shrug")));
+        assert_eq!(parse("/whois bob"), Ok(Input::Raw("whois bob")));
+        assert_eq!(parse("/"), Ok(Input::Text("/")));
+        assert_eq!(parse("/ shrug"), Ok(Input::Text("/ shrug")));
+    }
+
+    #[test]
+    fn bad_arguments_show_usage() {
+        let msg = find("msg").unwrap();
+        assert_eq!(parse("/msg bob"), Err(CommandError::Usage(msg)));
+        assert_eq!(
+            parse("/nick two words").unwrap_err().to_string(),
+            "Usage: /nick <name>"
+        );
+        assert_eq!(
+            parse("/join #"),
+            Err(CommandError::Usage(find("join").unwrap()))
+        );
+        assert_eq!(
+            parse("/clear all"),
+            Err(CommandError::Usage(find("clear").unwrap()))
+        );
+        assert_eq!(
+            parse("/help whois"),
+            Err(CommandError::NoHelp("whois".to_string()))
+        );
+    }
+
+    #[test]
+    fn names_are_matched_while_typed() {
+        let names = |line| matching(line).iter().map(|s| s.name).collect::<Vec<_>>();
+
+        assert_eq!(names("/c"), ["clear", "close"]);
+        assert_eq!(names("/").len(), COMMANDS.len());
+        assert!(names("/msg bob").is_empty());
+        assert!(names("//c").is_empty());
+        assert!(names("hi").is_empty());
+    }
+}
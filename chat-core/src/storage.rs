@@ -0,0 +1,138 @@
+//! Settings shared by every front end, kept in the file eframe persists the
+//! GUI's state to so that each sees what the others changed.
+
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// The saved servers, under the key eframe keeps the app's own state.
+pub const CONNECTIONS_KEY: &str = "app";
+pub const TIME_FORMAT_KEY: &str = "time_format";
+pub const NOTIFY_KEY: &str = "notify";
+
+/// Where everything the app keeps is stored.
+pub fn dir() -> Option<PathBuf> {
+    directories_next::ProjectDirs::from("", "", crate::APP_NAME)
+        .map(|dirs| dirs.data_dir().to_path_buf())
+}
+
+/// Values stored by key as RON, the way eframe stores them.
+#[derive(Default)]
+pub struct Storage {
+    /// Only kept in memory if `None`.
+    path: Option<PathBuf>,
+    values: HashMap<String, String>,
+}
+
+impl Storage {
+    /// Opens the storage in the default directory.
+    pub fn open() -> io::Result<Self> {
+        let dir = dir().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, "No home directory for settings")
+        })?;
+
+        Self::open_in(&dir)
+    }
+
+    pub fn open_in(dir: &Path) -> io::Result<Self> {
+        let path = dir.join("app.ron");
+
+        let values = match fs::read_to_string(&path) {
+            Ok(text) => {
+                ron::from_str(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            values,
+        })
+    }
+
+    /// The value stored under `key`, if there is one and it can be read.
+    pub fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let value = self.values.get(key)?;
+
+        ron::from_str(value)
+            .map_err(|e| tracing::warn!("Couldn't read setting {}: {}", key, e))
+            .ok()
+    }
+
+    pub fn set<T: Serialize>(&mut self, key: &str, value: &T) {
+        match ron::to_string(value) {
+            Ok(value) => {
+                self.values.insert(key.to_string(), value);
+            }
+            Err(e) => tracing::warn!("Couldn't store setting {}: {}", key, e),
+        }
+    }
+
+    /// Writes every value back, keeping whatever other front ends stored.
+    pub fn save(&self) -> io::Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        let text = ron::ser::to_string_pretty(&self.values, Default::default())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        fs::write(path, text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::net::connection::ConnectionData;
+
+    #[test]
+    fn values_survive_reopening() {
+        let dir = tempfile::tempdir().unwrap();
+        let connections = vec![ConnectionData::new("example.com:6078", "alice")];
+
+        let mut storage = Storage::open_in(dir.path()).unwrap();
+        assert!(storage
+            .get::<Vec<ConnectionData>>(CONNECTIONS_KEY)
+            .is_none());
+        storage.set(CONNECTIONS_KEY, &connections);
+        storage.save().unwrap();
+
+        let storage = Storage::open_in(dir.path()).unwrap();
+        let read: Vec<ConnectionData> = storage.get(CONNECTIONS_KEY).unwrap();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].server(), "example.com:6078");
+        assert_eq!(read[0].name(), "alice");
+    }
+
+    #[test]
+    fn other_values_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // As eframe writes them, with values that are RON themselves
+        fs::write(
+            dir.path().join("app.ron"),
+            r#"{ "show_roster": "false", "egui": "(window: None)" }"#,
+        )
+        .unwrap();
+
+        let mut storage = Storage::open_in(dir.path()).unwrap();
+        storage.set(TIME_FORMAT_KEY, &crate::timestamp::TimeFormat::default());
+        storage.save().unwrap();
+
+        let storage = Storage::open_in(dir.path()).unwrap();
+        assert_eq!(storage.get("show_roster"), Some(false));
+        assert_eq!(
+            storage.values.get("egui").map(String::as_str),
+            Some("(window: None)")
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use chrono::{DateTime, Local};
+use std::time::SystemTime;
+
+/// How message times are shown.
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Default)]
+pub enum TimeFormat {
+    /// How long ago, like `5m ago`.
+    Relative,
+    /// Local time of day, `HH:MM`.
+    #[default]
+    Clock,
+    /// Local date and time.
+    Full,
+}
+
+impl TimeFormat {
+    pub const ALL: [Self; 3] = [Self::Relative, Self::Clock, Self::Full];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Relative => "Relative",
+            Self::Clock => "HH:MM",
+            Self::Full => "Full date",
+        }
+    }
+
+    pub fn format(self, time: SystemTime, now: SystemTime) -> String {
+        match self {
+            Self::Relative => {
+                // Times from the server can be slightly ahead of ours
+                let secs = now.duration_since(time).unwrap_or_default().as_secs();
+
+                match secs {
+                    0..=59 => "just now".to_string(),
+                    60..=3599 => format!("{}m ago", secs / 60),
+                    3600..=86399 => format!("{}h ago", secs / 3600),
+                    _ => format!("{}d ago", secs / 86400),
+                }
+            }
+            Self::Clock => DateTime::<Local>::from(time).format("%H:%M").to_string(),
+            Self::Full => DateTime::<Local>::from(time)
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string(),
+        }
+    }
+}
+
+/// Whether both times are on the same local day.
+pub fn same_day(a: SystemTime, b: SystemTime) -> bool {
+    DateTime::<Local>::from(a).date_naive() == DateTime::<Local>::from(b).date_naive()
+}
+
+/// The local date, like `Monday, 18 October 2026`.
+pub fn day_label(time: SystemTime) -> String {
+    DateTime::<Local>::from(time)
+        .format("%A, %-d %B %Y")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::Duration;
+
+    #[test]
+    fn relative_times() {
+        let now = SystemTime::now();
+        let ago = |secs| TimeFormat::Relative.format(now - Duration::from_secs(secs), now);
+
+        assert_eq!(ago(5), "just now");
+        assert_eq!(ago(150), "2m ago");
+        assert_eq!(ago(3 * 3600 + 5), "3h ago");
+        assert_eq!(ago(2 * 86400), "2d ago");
+        assert_eq!(
+            TimeFormat::Relative.format(now + Duration::from_secs(3), now),
+            "just now"
+        );
+    }
+
+    #[test]
+    fn days_are_compared_locally() {
+        let noon =
+            crate::search::parse_date("2022-05-01").unwrap() + Duration::from_secs(12 * 3600);
+
+        assert!(same_day(noon, noon + Duration::from_secs(3600)));
+        assert!(!same_day(noon, noon + Duration::from_secs(24 * 3600)));
+        assert_eq!(day_label(noon), "Sunday, 1 May 2022");
+        assert_eq!(TimeFormat::Clock.format(noon, noon), "12:00");
+        assert_eq!(TimeFormat::Full.format(noon, noon), "2022-05-01 12:00:00");
+    }
+}
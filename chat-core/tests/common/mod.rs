@@ -0,0 +1,193 @@
+//! Harness for running the network task against a scripted fake server.
+
+// Each test file uses only some of it
+#![allow(dead_code)]
+
+use chat_core::net::{
+    client::{self, Waker},
+    commands::{ClientCommands, ClientRequest},
+    connection::{ConnectionData, ReconnectPolicy},
+    transport::Transport,
+};
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+    task::JoinHandle,
+    time,
+};
+use tokio_util::sync::CancellationToken;
+
+/// How long a test may run before it is considered stuck.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy)]
+pub enum Step {
+    /// Wait for the client to send this line.
+    Expect(&'static str),
+    /// Send the client this line.
+    Send(&'static str),
+    /// Send the client raw bytes.
+    SendBytes(&'static [u8]),
+    /// Wait for the client to ping, and answer it.
+    Pong,
+    /// Wait for the client to ping, without answering.
+    Ping,
+    /// Ignore everything the client sends until it hangs up.
+    Silence,
+}
+
+pub use Step::*;
+
+/// Plays a script over a connection, then closes it.
+pub async fn play<S: AsyncRead + AsyncWrite>(stream: S, script: Vec<Step>) {
+    let (reader, mut writer) = io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    for step in script {
+        match step {
+            Expect(expected) => {
+                let line = lines.next_line().await.unwrap();
+                assert_eq!(line.as_deref(), Some(expected));
+            }
+            Send(line) => {
+                writer.write_all(line.as_bytes()).await.unwrap();
+                writer.write_u8(b'\n').await.unwrap();
+            }
+            SendBytes(bytes) => writer.write_all(bytes).await.unwrap(),
+            Pong => {
+                let line = lines.next_line().await.unwrap().unwrap();
+                let token = line.strip_prefix("ping ").expect("expected a ping");
+
+                let pong = format!("pong {}\n", token);
+                writer.write_all(pong.as_bytes()).await.unwrap();
+            }
+            Ping => {
+                let line = lines.next_line().await.unwrap().unwrap();
+                assert!(line.starts_with("ping "), "expected a ping: {:?}", line);
+            }
+            Silence => while lines.next_line().await.unwrap_or(None).is_some() {},
+        }
+    }
+
+    writer.shutdown().await.unwrap();
+}
+
+/// A server on an ephemeral port playing one script per connection it
+/// accepts, which stops listening once every script has been used.
+pub struct ScriptedServer {
+    pub port: u16,
+    task: JoinHandle<()>,
+}
+
+impl ScriptedServer {
+    pub async fn start(scripts: Vec<Vec<Step>>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let task = tokio::spawn(async move {
+            for script in scripts {
+                let (stream, _) = listener.accept().await.unwrap();
+                play(stream, script).await;
+            }
+        });
+
+        Self { port, task }
+    }
+
+    /// Waits for every script to be played, failing if any step didn't match.
+    pub async fn finish(self) {
+        time::timeout(TIMEOUT, self.task)
+            .await
+            .expect("server didn't finish its scripts")
+            .unwrap();
+    }
+}
+
+/// Connection details for a local server on `port`, with the name `alice`.
+pub fn connection(port: u16, reconnect: bool) -> ConnectionData {
+    connection_to(&format!("127.0.0.1:{}", port), reconnect)
+}
+
+/// Connection details for `server`, with the name `alice` and quick retries.
+pub fn connection_to(server: &str, reconnect: bool) -> ConnectionData {
+    let mut connection = ConnectionData::new(server, "alice");
+    connection.set_reconnect(ReconnectPolicy {
+        enabled: reconnect,
+        initial_delay: Duration::from_millis(10),
+        max_delay: Duration::from_millis(10),
+        max_attempts: Some(1),
+    });
+
+    connection
+}
+
+/// A port nothing is listening on.
+pub async fn closed_port() -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    listener.local_addr().unwrap().port()
+}
+
+/// Runs the network task until it gives up, having queued `requests` for it
+/// to send, and returns everything it reported to the tab.
+///
+/// Receive times are checked and then set to the epoch, so the commands can be
+/// compared with `ClientCommands` built by the test.
+pub async fn run_client(
+    transport: Option<&dyn Transport>,
+    connection: ConnectionData,
+    requests: Vec<ClientRequest>,
+) -> Vec<ClientCommands> {
+    let (tab_send, client_recv) = mpsc::channel(100);
+    let (client_send, mut tab_recv) = mpsc::channel(100);
+
+    for request in requests {
+        tab_send.send(request).await.unwrap();
+    }
+
+    let waker = Waker::default();
+    let cancel = CancellationToken::new();
+    let network = async {
+        match transport {
+            Some(transport) => {
+                client::network_with(
+                    transport,
+                    client_send,
+                    client_recv,
+                    waker,
+                    connection,
+                    cancel,
+                )
+                .await
+            }
+            None => client::network(client_send, client_recv, waker, connection, cancel).await,
+        }
+    };
+
+    time::timeout(TIMEOUT, network)
+        .await
+        .expect("network task didn't finish");
+
+    // Only dropped now, as the tab going away also ends the connection
+    drop(tab_send);
+
+    let mut commands = Vec::new();
+    while let Ok(mut command) = tab_recv.try_recv() {
+        if let ClientCommands::ChatCommand(record) = &mut command {
+            let age = SystemTime::now().duration_since(record.time).unwrap();
+            assert!(
+                age < TIMEOUT,
+                "{:?} wasn't received during the test",
+                record
+            );
+
+            record.time = UNIX_EPOCH;
+        }
+
+        commands.push(command);
+    }
+
+    commands
+}
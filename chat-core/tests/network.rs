@@ -0,0 +1,404 @@
+mod common;
+
+use common::*;
+
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use chat_core::net::{
+    client::{NetworkTask, Waker, SHUTDOWN_GRACE},
+    commands::{ChatCommands, ClientCommands, ClientRequest, ConnectState, Record},
+    connection::{ConnectionData, Keepalive, ReconnectPolicy},
+    error::NetError,
+    transport::Memory,
+};
+use tokio::{runtime::Runtime, sync::mpsc, time};
+
+fn state(state: ConnectState) -> ClientCommands {
+    ClientCommands::ConnectState(state)
+}
+
+fn record(command: ChatCommands) -> ClientCommands {
+    ClientCommands::ChatCommand(Record {
+        time: UNIX_EPOCH,
+        server_time: None,
+        command,
+    })
+}
+
+fn chat(line: &str) -> ClientCommands {
+    record(line.parse().unwrap())
+}
+
+/// Pings every 20ms, giving up after `timeout` of silence.
+fn with_pings(mut connection: ConnectionData, timeout: Duration) -> ConnectionData {
+    connection.set_keepalive(Keepalive {
+        enabled: true,
+        interval: Duration::from_millis(20),
+        timeout,
+    });
+
+    connection
+}
+
+#[tokio::test]
+async fn connect_sends_name() {
+    let server = ScriptedServer::start(vec![vec![Expect("alice"), Send("c alice")]]).await;
+
+    let commands = run_client(None, connection(server.port, false), vec![]).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("c alice"),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn messages_are_sent_and_received() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Expect("m hello everyone"),
+        Send("m alice hello everyone"),
+        Send("m bob hi alice"),
+    ]])
+    .await;
+
+    let requests = vec![ClientRequest::Message {
+        message: "hello everyone".to_string(),
+    }];
+    let commands = run_client(None, connection(server.port, false), requests).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("m alice hello everyone"),
+            record(ChatCommands::Message {
+                sender: "bob".to_string(),
+                message: "hi alice".to_string(),
+            }),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn rename_and_raw_commands() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Expect("n alicia"),
+        Expect("x something"),
+        Send("r alice alicia"),
+    ]])
+    .await;
+
+    let requests = vec![
+        ClientRequest::Rename {
+            name: "alicia".to_string(),
+        },
+        ClientRequest::Raw("x something".to_string()),
+    ];
+    let commands = run_client(None, connection(server.port, false), requests).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("r alice alicia"),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn server_close_disconnects() {
+    let server = ScriptedServer::start(vec![vec![]]).await;
+
+    let commands = run_client(None, connection(server.port, false), vec![]).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn server_times_are_kept() {
+    let server = ScriptedServer::start(vec![vec![Send("@1650000000000 c bob")]]).await;
+
+    let commands = run_client(None, connection(server.port, false), vec![]).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands[1],
+        ClientCommands::ChatCommand(Record {
+            time: UNIX_EPOCH,
+            server_time: Some(UNIX_EPOCH + Duration::from_secs(1_650_000_000)),
+            command: "c bob".parse().unwrap(),
+        })
+    );
+}
+
+#[tokio::test]
+async fn refused_connection_fails() {
+    let port = closed_port().await;
+
+    let commands = run_client(None, connection(port, false), vec![]).await;
+
+    assert_eq!(
+        commands,
+        vec![
+            ClientCommands::Error(NetError::Refused),
+            state(ConnectState::Failed),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn unresolvable_server_fails() {
+    let connection = connection_to("doesnotexist.invalid:6078", false);
+
+    let commands = run_client(None, connection, vec![]).await;
+
+    assert_eq!(
+        commands,
+        vec![
+            ClientCommands::Error(NetError::Resolve("doesnotexist.invalid:6078".to_string())),
+            state(ConnectState::Failed),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn protocol_errors_disconnect() {
+    let server =
+        ScriptedServer::start(vec![vec![Send("c bob"), Send("nonsense"), Send("c carol")]]).await;
+
+    let commands = run_client(None, connection(server.port, false), vec![]).await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("c bob"),
+            ClientCommands::Error(NetError::Protocol("nonsense".to_string())),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn invalid_utf8_disconnects() {
+    let server = ScriptedServer::start(vec![vec![SendBytes(b"m bob \xff\xfe\n")]]).await;
+
+    let commands = run_client(None, connection(server.port, false), vec![]).await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            ClientCommands::Error(NetError::InvalidUtf8),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn reconnects_after_server_close() {
+    let server = ScriptedServer::start(vec![
+        vec![Expect("alice"), Send("c alice")],
+        vec![Expect("alice"), Send("c alice")],
+    ])
+    .await;
+
+    let commands = run_client(None, connection(server.port, true), vec![]).await;
+    server.finish().await;
+
+    // Attempts only count failures in a row, so the client keeps going until
+    // the server stops listening
+    let reconnecting = |command: &ClientCommands| {
+        matches!(
+            command,
+            ClientCommands::ConnectState(ConnectState::Reconnecting { attempt: 1, .. })
+        )
+    };
+
+    assert_eq!(commands.len(), 8, "{:?}", commands);
+    assert_eq!(commands[0], state(ConnectState::Connected));
+    assert_eq!(commands[1], chat("c alice"));
+    assert!(reconnecting(&commands[2]));
+    assert_eq!(commands[3], state(ConnectState::Connected));
+    assert_eq!(commands[4], chat("c alice"));
+    assert!(reconnecting(&commands[5]));
+    assert_eq!(commands[6], ClientCommands::Error(NetError::Refused));
+    assert_eq!(commands[7], state(ConnectState::Failed));
+}
+
+#[tokio::test]
+async fn memory_transport() {
+    let (transport, mut accept) = Memory::new();
+    let server = tokio::spawn(async move {
+        let stream = accept.recv().await.unwrap();
+        play(stream, vec![Expect("alice"), Send("d bob")]).await;
+    });
+
+    let commands = run_client(Some(&transport), connection(0, false), vec![]).await;
+    server.await.unwrap();
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("d bob"),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn pongs_report_latency() {
+    let server = ScriptedServer::start(vec![vec![Expect("alice"), Pong]]).await;
+
+    let connection = with_pings(connection(server.port, false), TIMEOUT);
+    let commands = run_client(None, connection, vec![]).await;
+    server.finish().await;
+
+    assert_eq!(commands.len(), 3, "{:?}", commands);
+    assert_eq!(commands[0], state(ConnectState::Connected));
+    assert!(
+        matches!(commands[1], ClientCommands::Latency(latency) if latency < TIMEOUT),
+        "{:?}",
+        commands[1]
+    );
+    assert_eq!(commands[2], state(ConnectState::Disconnect));
+}
+
+#[tokio::test]
+async fn silent_server_times_out() {
+    let server = ScriptedServer::start(vec![vec![Expect("alice"), Pong, Silence]]).await;
+
+    let connection = with_pings(connection(server.port, false), Duration::from_millis(100));
+    let commands = run_client(None, connection, vec![]).await;
+    server.finish().await;
+
+    assert_eq!(commands.len(), 4, "{:?}", commands);
+    assert!(matches!(commands[1], ClientCommands::Latency(_)));
+    assert_eq!(
+        commands[2..],
+        [
+            ClientCommands::Error(NetError::TimedOut),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn servers_without_pongs_stay_connected() {
+    // Quiet for well past the timeout, as pings go unanswered
+    let mut script = vec![Expect("alice")];
+    script.extend([Ping; 10]);
+    script.push(Send("c alice"));
+    let server = ScriptedServer::start(vec![script]).await;
+
+    let connection = with_pings(connection(server.port, false), Duration::from_millis(100));
+    let commands = run_client(None, connection, vec![]).await;
+    server.finish().await;
+
+    assert_eq!(
+        commands,
+        vec![
+            state(ConnectState::Connected),
+            chat("c alice"),
+            state(ConnectState::Disconnect),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn stopping_says_goodbye() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Send("c alice"),
+        Expect("quit"),
+        Silence,
+    ]])
+    .await;
+
+    let (_tab_send, client_recv) = mpsc::channel(100);
+    let (client_send, mut tab_recv) = mpsc::channel(100);
+    let mut task = NetworkTask::spawn(
+        client_send,
+        client_recv,
+        Waker::default(),
+        connection(server.port, true),
+    );
+
+    assert_eq!(tab_recv.recv().await, Some(state(ConnectState::Connected)));
+    assert!(matches!(
+        tab_recv.recv().await,
+        Some(ClientCommands::ChatCommand(_))
+    ));
+
+    let stopped = task.stop().unwrap();
+    time::timeout(TIMEOUT, stopped).await.unwrap().unwrap();
+    server.finish().await;
+    assert!(task.stop().is_none());
+
+    // Nothing else is reported, and the task is gone rather than reconnecting
+    assert_eq!(tab_recv.recv().await, None);
+}
+
+#[tokio::test]
+async fn dropping_stops_reconnecting() {
+    let mut connection = connection(closed_port().await, true);
+    connection.set_reconnect(ReconnectPolicy {
+        enabled: true,
+        initial_delay: Duration::from_secs(60),
+        max_delay: Duration::from_secs(60),
+        max_attempts: None,
+    });
+
+    let (_tab_send, client_recv) = mpsc::channel(100);
+    let (client_send, mut tab_recv) = mpsc::channel(100);
+    let task = NetworkTask::spawn(client_send, client_recv, Waker::default(), connection);
+
+    loop {
+        let command = time::timeout(TIMEOUT, tab_recv.recv()).await.unwrap();
+        if let Some(ClientCommands::ConnectState(ConnectState::Reconnecting { .. })) = command {
+            break;
+        }
+    }
+
+    // The task finishes by itself well before it would have been aborted
+    let start = Instant::now();
+    drop(task);
+    assert_eq!(tab_recv.recv().await, None);
+    assert!(start.elapsed() < SHUTDOWN_GRACE);
+}
+
+#[test]
+fn tasks_can_be_dropped_outside_the_runtime() {
+    let runtime = Runtime::new().unwrap();
+    let (_tab_send, client_recv) = mpsc::channel(100);
+    let (client_send, mut tab_recv) = mpsc::channel(100);
+
+    let task = {
+        let _runtime = runtime.enter();
+        let connection = connection_to("127.0.0.1:0", true);
+        NetworkTask::spawn(client_send, client_recv, Waker::default(), connection)
+    };
+    drop(task);
+
+    // The task is aborted rather than left running
+    assert_eq!(runtime.block_on(tab_recv.recv()), None);
+}
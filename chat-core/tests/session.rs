@@ -0,0 +1,436 @@
+mod common;
+
+use common::*;
+
+use std::{path::Path, sync::Arc, time::Duration};
+
+use chat_core::{
+    history::{History, Retention},
+    net::{
+        client::Waker,
+        commands::{ConnectState, Record},
+        connection::{ConnectionData, ReconnectPolicy},
+        error::NetError,
+    },
+    notify::{Notification, NotifySettings},
+    search::Query,
+    session::{Chat, Persistence, Session, View},
+    slash::Command,
+};
+use tokio::{sync::Notify, time};
+
+/// A session with `dir` for its history, and what its waker notifies.
+fn start(port: u16, dir: &Path) -> (Session, Arc<Notify>) {
+    start_with(connection(port, false), dir)
+}
+
+fn start_with(connection: ConnectionData, dir: &Path) -> (Session, Arc<Notify>) {
+    let woken = Arc::new(Notify::new());
+    let waker = {
+        let woken = woken.clone();
+        Waker::new(move || woken.notify_one())
+    };
+
+    let persistence = Persistence::In(dir.to_path_buf());
+    let session = Session::new_with(persistence, connection, waker);
+    (session, woken)
+}
+
+/// Syncs `session` each time it is woken until `done` holds.
+async fn sync_until(
+    session: &mut Session,
+    woken: &Notify,
+    notifications: &mut Vec<Notification>,
+    done: impl Fn(&Session) -> bool,
+) {
+    let settings = NotifySettings::default();
+
+    let sync = async {
+        loop {
+            session.sync_messages(&settings, notifications);
+            if done(session) {
+                break;
+            }
+
+            woken.notified().await;
+        }
+    };
+
+    time::timeout(TIMEOUT, sync)
+        .await
+        .expect("session never got there");
+}
+
+#[tokio::test]
+async fn sessions_file_what_they_receive() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Send("c alice"),
+        Send("m bob hi alice"),
+        Send("pm carol psst"),
+        Expect("m hello"),
+        Expect("quit"),
+        Silence,
+    ]])
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, woken) = start(server.port, dir.path());
+    let mut notifications = Vec::new();
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        !session.conversations.is_empty()
+    })
+    .await;
+
+    assert_eq!(session.connect_state, ConnectState::Connected);
+    assert!(session.roster.contains("alice"));
+    assert_eq!(session.chat.messages().len(), 2);
+    assert_eq!(session.conversations[0].peer, "carol");
+    assert_eq!(session.unread(), (2, 2));
+    assert_eq!(notifications.len(), 2);
+    assert_eq!(notifications[1].summary, "carol messaged you");
+
+    // Reading the direct message leaves only the server's unread
+    session.view = View::Conversation(0);
+    session.current_chat().mark_read(false);
+    assert_eq!(session.unread(), (1, 1));
+
+    session.view = View::Server;
+    session.current_chat().message = "hello".to_string();
+    session.send_message();
+    assert!(session.chat.message.is_empty());
+    assert!(session.pending().is_empty());
+
+    // Dropping the session says goodbye to the server
+    drop(session);
+    server.finish().await;
+}
+
+#[tokio::test]
+async fn commands_run_in_the_client() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Send("c alice"),
+        Expect("m /me waves"),
+        Send("m bob hi"),
+        Send("m carol hey"),
+        Expect("whois bob"),
+        Expect("quit"),
+        Silence,
+    ]])
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, woken) = start(server.port, dir.path());
+    let mut notifications = Vec::new();
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.connect_state == ConnectState::Connected
+    })
+    .await;
+
+    let type_in = |session: &mut Session, line: &str| {
+        session.current_chat().message = line.to_string();
+        session.send_message()
+    };
+
+    // Mistakes are left to be fixed
+    assert_eq!(type_in(&mut session, "/msg bob"), None);
+    assert_eq!(session.chat.message, "/msg bob");
+    assert_eq!(
+        session.command_error.as_deref(),
+        Some("Usage: /msg <name> <message>")
+    );
+
+    assert_eq!(type_in(&mut session, "/join #  "), None);
+    assert_eq!(session.view, View::Server);
+    assert_eq!(
+        session.command_error.as_deref(),
+        Some("Usage: /join <room>")
+    );
+    assert!(session.rooms.is_empty());
+
+    assert_eq!(type_in(&mut session, "/ignore bob"), None);
+    assert!(session.connection.is_ignored("bob"));
+    assert_eq!(type_in(&mut session, "/me waves"), None);
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.chat.messages().len() == 2
+    })
+    .await;
+    assert_eq!(session.chat.messages()[1].command.text(), "hey");
+
+    assert_eq!(type_in(&mut session, "/whois bob"), None);
+    assert_eq!(type_in(&mut session, "/close"), Some(Command::Close));
+
+    type_in(&mut session, "/clear");
+    assert!(session.chat.messages().is_empty());
+    assert_eq!(session.chat.read_back(10), 0);
+
+    drop(session);
+    server.finish().await;
+}
+
+#[tokio::test]
+async fn errors_clear_on_reconnecting() {
+    let server = ScriptedServer::start(vec![
+        vec![Expect("alice"), Send("nonsense")],
+        vec![Expect("alice"), Send("c alice"), Expect("quit"), Silence],
+    ])
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    // Slow enough to see the error before it's got past
+    let mut connection = connection(server.port, true);
+    connection.set_reconnect(ReconnectPolicy {
+        initial_delay: Duration::from_millis(200),
+        ..connection.reconnect().clone()
+    });
+    let (mut session, woken) = start_with(connection, dir.path());
+    let mut notifications = Vec::new();
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.error.is_some()
+    })
+    .await;
+    assert_eq!(
+        session.error,
+        Some(NetError::Protocol("nonsense".to_string()))
+    );
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.roster.contains("alice")
+    })
+    .await;
+    assert_eq!(session.connect_state, ConnectState::Connected);
+    assert_eq!(session.error, None);
+
+    drop(session);
+    server.finish().await;
+}
+
+#[tokio::test]
+async fn conversations_follow_renames() {
+    let server = ScriptedServer::start(vec![vec![
+        Expect("alice"),
+        Send("c alice"),
+        Send("pm bob hi"),
+        Send("pm robert yo"),
+        Send("r bob robert"),
+        Send("pm robert again"),
+        Expect("quit"),
+        Silence,
+    ]])
+    .await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, woken) = start(server.port, dir.path());
+    let mut notifications = Vec::new();
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session
+            .conversations
+            .first()
+            .is_some_and(|c| c.chat.messages().len() == 4)
+    })
+    .await;
+
+    // Both conversations end up as one under the new name
+    assert_eq!(session.conversations.len(), 1);
+    assert_eq!(session.conversations[0].peer, "robert");
+    let texts = session.conversations[0]
+        .chat
+        .messages()
+        .iter()
+        .map(|record| record.command.text())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["hi", "yo", "bob changed names to robert", "again"]);
+
+    let name = session.conversations[0].chat.name().to_string();
+    drop(session);
+    server.finish().await;
+
+    // Which is where it's found next time
+    let history = dir.path().join("history");
+    let mut history = History::open_in(&history, &name).unwrap();
+    assert_eq!(history.load(&Retention::default()).unwrap().len(), 4);
+    let files = std::fs::read_dir(dir.path().join("history"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|file| file.ends_with(".log"))
+        .collect::<Vec<_>>();
+    assert_eq!(files.len(), 2, "{:?}", files);
+}
+
+#[tokio::test]
+async fn outbox_drains_in_order() {
+    // More than fits in the send queue at once
+    let lines = (0..150)
+        .map(|i| format!("m {}", i))
+        .chain(["m last".to_string()])
+        .collect::<Vec<_>>();
+    let mut script = vec![Expect("alice"), Send("c alice")];
+    script.extend(lines.iter().map(|line| Expect(line.clone().leak())));
+    script.extend([Expect("quit"), Silence]);
+    let server = ScriptedServer::start(vec![script]).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, woken) = start(server.port, dir.path());
+    let mut notifications = Vec::new();
+
+    for i in 0..150 {
+        session.current_chat().message = i.to_string();
+        session.send_message();
+    }
+    assert_eq!(session.pending().len(), 150);
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.connect_state == ConnectState::Connected
+    })
+    .await;
+
+    // Anything new waits behind what is still queued
+    assert!(!session.pending().is_empty());
+    session.current_chat().message = "last".to_string();
+    session.send_message();
+
+    let settings = NotifySettings::default();
+    let drained = async {
+        while !session.pending().is_empty() {
+            time::sleep(Duration::from_millis(10)).await;
+            session.sync_messages(&settings, &mut notifications);
+        }
+    };
+    time::timeout(TIMEOUT, drained)
+        .await
+        .expect("outbox never drained");
+
+    drop(session);
+    server.finish().await;
+}
+
+#[test]
+fn searches_reach_paged_out_messages() {
+    let dir = tempfile::tempdir().unwrap();
+    let retention = Retention {
+        in_memory: 3,
+        ..Retention::default()
+    };
+    let mut chat = Chat::open_in(dir.path(), "server", &retention);
+
+    for line in [
+        "m bob hello",
+        "m carol hi",
+        "m bob hello again",
+        "m carol bye",
+    ] {
+        let record = line.parse::<Record>().unwrap();
+        chat.push(record);
+    }
+    for _ in 0..4 {
+        chat.push("c dave".parse().unwrap());
+    }
+    assert_eq!(chat.paged_out(), 5);
+
+    let query = Query {
+        text: "hello".to_string(),
+        ..Query::default()
+    };
+    assert_eq!(chat.search(&query), [0, 2]);
+    let found = chat.rows(1..3);
+    assert_eq!(found[1].command.text(), "hello again");
+
+    // Both the history and memory are searched
+    let query = Query {
+        sender: "dave".to_string(),
+        ..Query::default()
+    };
+    assert_eq!(chat.search(&query), [4, 5, 6, 7]);
+    assert_eq!(chat.rows(4..10).len(), 4);
+
+    // Cleared messages aren't found
+    chat.clear();
+    assert!(chat.search(&query).is_empty());
+    assert!(chat.rows(0..10).is_empty());
+}
+
+#[tokio::test]
+async fn edits_keep_what_they_leave_alone() {
+    let dir = tempfile::tempdir().unwrap();
+    let port = closed_port().await;
+    let (mut session, _) = start(port, dir.path());
+    session.connection.set_muted(true);
+    session.connection.ignore("bob");
+    session
+        .connection
+        .set_fingerprint(Some("ab:cd".to_string()));
+
+    session.edit_connection(|connection| connection.set_name("alicia"));
+    assert_eq!(session.connection.name(), "alicia");
+    assert_eq!(session.connection.fingerprint(), Some("ab:cd"));
+
+    // A pinned certificate only belongs to the server it came from
+    let other = format!("localhost:{}", port);
+    session.edit_connection(|connection| connection.set_server(&other));
+    assert_eq!(session.connection.server(), &other);
+    assert_eq!(session.connection.name(), "alicia");
+    assert!(session.connection.muted());
+    assert!(session.connection.is_ignored("bob"));
+    assert_eq!(session.connection.fingerprint(), None);
+}
+
+#[tokio::test]
+async fn tab_completes_commands_then_names() {
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, _) = start(closed_port().await, dir.path());
+    session.nicks.apply(&"u alice bob barbara".parse().unwrap());
+
+    let type_in = |session: &mut Session, line: &str| {
+        session.current_chat().message = line.to_string();
+        session.complete(line.chars().count())
+    };
+
+    assert_eq!(type_in(&mut session, "/he"), Some(6));
+    assert_eq!(session.chat.message, "/help ");
+    assert_eq!(type_in(&mut session, "/cl"), None);
+
+    // Our own name isn't offered
+    assert_eq!(type_in(&mut session, "a"), None);
+
+    assert_eq!(type_in(&mut session, "b"), Some(5));
+    assert_eq!(session.chat.message, "bob: ");
+    assert_eq!(session.complete(5), Some(9));
+    assert_eq!(session.chat.message, "barbara: ");
+    assert_eq!(session.select_completion(0), Some(5));
+    assert_eq!(session.chat.message, "bob: ");
+}
+
+#[tokio::test]
+async fn full_buffers_give_back_what_was_typed() {
+    let server = ScriptedServer::start(vec![vec![Expect("alice"), Send("c alice"), Silence]]).await;
+
+    let dir = tempfile::tempdir().unwrap();
+    let (mut session, woken) = start(server.port, dir.path());
+    let mut notifications = Vec::new();
+
+    sync_until(&mut session, &woken, &mut notifications, |session| {
+        session.connect_state == ConnectState::Connected
+    })
+    .await;
+
+    // Nothing is taken from the queue without yielding to the network task
+    while session.notice.is_none() {
+        session.current_chat().message = "filler".to_string();
+        session.send_message();
+    }
+
+    for line in ["/me waves", "//shrug", "/msg bob hi"] {
+        session.current_chat().message = line.to_string();
+        session.send_message();
+        assert_eq!(session.current_chat().message, line);
+    }
+
+    drop(session);
+    server.finish().await;
+}
@@ -0,0 +1,504 @@
+//! Reference server for the chat protocol, for running the client locally.
+//!
+//! Usage: `chat-server [--tls] [address]`, where the address is `host:port`
+//! (by default `0.0.0.0:6078`) or `unix:/path/to/socket`. With `--tls` a fresh
+//! self-signed certificate is generated and its fingerprint printed, so it can
+//! be compared with the one the client asks to trust.
+
+#![warn(clippy::all, rust_2018_idioms)]
+
+use chat_core::net::{
+    commands::{ChatCommands, ClientRequest},
+    tls,
+};
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    env, process,
+    sync::{Arc, Mutex},
+};
+use tokio::{
+    io::{self, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpListener,
+    sync::mpsc,
+};
+use tokio_rustls::{
+    rustls::{crypto, pki_types::PrivateKeyDer, ServerConfig},
+    TlsAcceptor,
+};
+
+struct User {
+    name: String,
+    send: mpsc::UnboundedSender<ChatCommands>,
+    rooms: BTreeSet<String>,
+}
+
+/// Everyone currently connected, by connection id.
+#[derive(Default)]
+struct Users {
+    next_id: usize,
+    users: HashMap<usize, User>,
+}
+
+type SharedUsers = Arc<Mutex<Users>>;
+
+impl Users {
+    /// Adds a user, telling them who else is online.
+    fn join(&mut self, name: String, send: mpsc::UnboundedSender<ChatCommands>) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut names = self
+            .users
+            .values()
+            .map(|user| user.name.clone())
+            .collect::<Vec<_>>();
+        names.sort();
+        let _ = send.send(ChatCommands::UserList { names });
+
+        self.users.insert(
+            id,
+            User {
+                name,
+                send,
+                rooms: BTreeSet::new(),
+            },
+        );
+        id
+    }
+
+    fn broadcast(&self, command: ChatCommands) {
+        for User { send, .. } in self.users.values() {
+            // Users who just left are cleaned up by their own connection
+            let _ = send.send(command.clone());
+        }
+    }
+
+    fn in_room<'a>(&'a self, room: &'a str) -> impl Iterator<Item = &'a User> {
+        self.users
+            .values()
+            .filter(move |user| user.rooms.contains(room))
+    }
+
+    fn broadcast_room(&self, room: &str, command: ChatCommands) {
+        for User { send, .. } in self.in_room(room) {
+            let _ = send.send(command.clone());
+        }
+    }
+
+    /// Every room with someone in it.
+    fn rooms(&self) -> Vec<String> {
+        let rooms = self
+            .users
+            .values()
+            .flat_map(|user| user.rooms.iter().cloned())
+            .collect::<BTreeSet<_>>();
+
+        rooms.into_iter().collect()
+    }
+}
+
+/// Names are sent as single words, so any whitespace is replaced.
+fn clean_name(name: &str) -> String {
+    let name = name.split_whitespace().collect::<Vec<_>>().join("_");
+
+    if name.is_empty() {
+        "nobody".to_string()
+    } else {
+        name
+    }
+}
+
+async fn handle<S>(stream: S, users: SharedUsers) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let (reader, mut writer) = io::split(stream);
+    let mut lines = BufReader::new(reader).lines();
+
+    // The first line is the user's name
+    let mut name = match lines.next_line().await? {
+        Some(line) => clean_name(&line),
+        None => return Ok(()),
+    };
+
+    let (send, mut recv) = mpsc::unbounded_channel::<ChatCommands>();
+    let id = {
+        let mut users = users.lock().unwrap();
+        let id = users.join(name.clone(), send);
+        users.broadcast(ChatCommands::UserConnected { name: name.clone() });
+        id
+    };
+
+    tokio::spawn(async move {
+        while let Some(command) = recv.recv().await {
+            let line = format!("{}\n", command);
+
+            if writer.write_all(line.as_bytes()).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = async {
+        while let Some(line) = lines.next_line().await? {
+            let mut users = users.lock().unwrap();
+
+            match line.parse::<ClientRequest>() {
+                Ok(ClientRequest::Message { message }) => {
+                    users.broadcast(ChatCommands::Message {
+                        sender: name.clone(),
+                        message,
+                    });
+                }
+
+                Ok(ClientRequest::Rename { name: newname }) => {
+                    let newname = clean_name(&newname);
+                    users.broadcast(ChatCommands::UserRenamed {
+                        oldname: name.clone(),
+                        newname: newname.clone(),
+                    });
+
+                    if let Some(user) = users.users.get_mut(&id) {
+                        user.name = newname.clone();
+                    }
+                    name = newname;
+                }
+
+                Ok(ClientRequest::PrivateMessage { target, message }) => {
+                    match users.users.values().find(|user| user.name == target) {
+                        Some(user) => {
+                            let _ = user.send.send(ChatCommands::PrivateMessage {
+                                sender: name.clone(),
+                                message,
+                            });
+                        }
+                        None => tracing::warn!("{}: no user {:?} to message", name, target),
+                    }
+                }
+
+                Ok(ClientRequest::Join { room }) => {
+                    let room = clean_name(&room);
+                    if users.users[&id].rooms.contains(&room) {
+                        continue;
+                    }
+
+                    let mut names = users
+                        .in_room(&room)
+                        .map(|user| user.name.clone())
+                        .collect::<Vec<_>>();
+                    names.sort();
+
+                    let user = users.users.get_mut(&id).unwrap();
+                    let _ = user.send.send(ChatCommands::RoomUsers {
+                        room: room.clone(),
+                        names,
+                    });
+                    user.rooms.insert(room.clone());
+
+                    users.broadcast_room(
+                        &room,
+                        ChatCommands::UserJoined {
+                            room: room.clone(),
+                            name: name.clone(),
+                        },
+                    );
+                }
+
+                Ok(ClientRequest::Leave { room }) => {
+                    let room = clean_name(&room);
+                    if !users.users[&id].rooms.contains(&room) {
+                        continue;
+                    }
+
+                    users.broadcast_room(
+                        &room,
+                        ChatCommands::UserLeft {
+                            room: room.clone(),
+                            name: name.clone(),
+                        },
+                    );
+                    users.users.get_mut(&id).unwrap().rooms.remove(&room);
+                }
+
+                Ok(ClientRequest::RoomMessage { room, message }) => {
+                    let room = clean_name(&room);
+                    if users.users[&id].rooms.contains(&room) {
+                        users.broadcast_room(
+                            &room,
+                            ChatCommands::RoomMessage {
+                                room: room.clone(),
+                                sender: name.clone(),
+                                message,
+                            },
+                        );
+                    } else {
+                        tracing::warn!("{}: not in room {:?}", name, room);
+                    }
+                }
+
+                Ok(ClientRequest::Ping { token }) => {
+                    let _ = users.users[&id].send.send(ChatCommands::Pong { token });
+                }
+
+                Ok(ClientRequest::ListRooms) => {
+                    let _ = users.users[&id].send.send(ChatCommands::RoomList {
+                        rooms: users.rooms(),
+                    });
+                }
+
+                Ok(ClientRequest::Quit) => break,
+
+                _ => tracing::warn!("{}: ignoring unknown command {:?}", name, line),
+            }
+        }
+
+        Ok(())
+    }
+    .await;
+
+    let mut users = users.lock().unwrap();
+    users.users.remove(&id);
+    users.broadcast(ChatCommands::UserDisconnected { name });
+
+    result
+}
+
+fn self_signed_acceptor() -> TlsAcceptor {
+    let key = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])
+        .expect("failed to generate certificate");
+    let cert = key.cert.der().clone();
+
+    tracing::info!("Certificate fingerprint: {}", tls::fingerprint(&cert));
+
+    let config = ServerConfig::builder_with_provider(Arc::new(crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .and_then(|config| {
+            config.with_no_client_auth().with_single_cert(
+                vec![cert],
+                PrivateKeyDer::try_from(key.signing_key.serialize_der()).unwrap(),
+            )
+        })
+        .expect("failed to set up TLS");
+
+    TlsAcceptor::from(Arc::new(config))
+}
+
+#[cfg(unix)]
+async fn serve_unix(path: &str, users: SharedUsers) -> io::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let listener = tokio::net::UnixListener::bind(path)?;
+    tracing::info!("Listening on unix:{}", path);
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle(stream, users.clone()));
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_unix(_path: &str, _users: SharedUsers) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "Unix sockets aren't supported on this platform",
+    ))
+}
+
+async fn serve_tcp(address: &str, tls: bool, users: SharedUsers) -> io::Result<()> {
+    let acceptor = tls.then(self_signed_acceptor);
+    let listener = TcpListener::bind(address).await?;
+    tracing::info!("Listening on {}", listener.local_addr()?);
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let users = users.clone();
+
+        match acceptor.clone() {
+            Some(acceptor) => tokio::spawn(async move {
+                match acceptor.accept(stream).await {
+                    Ok(stream) => handle(stream, users).await,
+                    Err(e) => {
+                        tracing::warn!("{}: TLS handshake failed: {}", peer, e);
+                        Ok(())
+                    }
+                }
+            }),
+            None => tokio::spawn(handle(stream, users)),
+        };
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    let mut address = "0.0.0.0:6078".to_string();
+    let mut tls = false;
+    let mut log_level = None;
+
+    let usage = "Usage: chat-server [--tls] [--log-level LEVEL] [host:port | unix:/path]";
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--tls" => tls = true,
+            "--log-level" => match args.next().map(|level| level.parse::<tracing::Level>()) {
+                Some(Ok(level)) => log_level = Some(level),
+                _ => {
+                    eprintln!("{}", usage);
+                    process::exit(2);
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", usage);
+                return;
+            }
+            _ => address = arg,
+        }
+    }
+
+    // Log to stdout, as the clients do
+    match log_level {
+        Some(level) => tracing_subscriber::fmt().with_max_level(level).init(),
+        None => tracing_subscriber::fmt::init(),
+    }
+
+    let users = SharedUsers::default();
+    let result = match address.strip_prefix("unix:") {
+        Some(path) => serve_unix(path, users).await,
+        None => serve_tcp(&address, tls, users).await,
+    };
+
+    if let Err(e) = result {
+        tracing::error!("{}", e);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct TestClient {
+        lines: io::Lines<BufReader<io::ReadHalf<io::DuplexStream>>>,
+        writer: io::WriteHalf<io::DuplexStream>,
+    }
+
+    impl TestClient {
+        async fn connect(users: &SharedUsers, name: &str) -> Self {
+            let (client, server) = io::duplex(1024);
+            tokio::spawn(handle(server, users.clone()));
+
+            let (reader, writer) = io::split(client);
+            let mut client = Self {
+                lines: BufReader::new(reader).lines(),
+                writer,
+            };
+            client.send(name).await;
+            client
+        }
+
+        async fn send(&mut self, line: &str) {
+            self.writer.write_all(line.as_bytes()).await.unwrap();
+            self.writer.write_u8(b'\n').await.unwrap();
+        }
+
+        async fn expect(&mut self, line: &str) {
+            assert_eq!(self.lines.next_line().await.unwrap().as_deref(), Some(line));
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_broadcast() {
+        let users = SharedUsers::default();
+
+        let mut alice = TestClient::connect(&users, "alice").await;
+        alice.expect("u ").await;
+        alice.expect("c alice").await;
+
+        let mut bob = TestClient::connect(&users, "bob smith").await;
+        alice.expect("c bob_smith").await;
+        bob.expect("u alice").await;
+        bob.expect("c bob_smith").await;
+
+        alice.send("ping 42").await;
+        alice.expect("pong 42").await;
+
+        alice.send("m hi bob").await;
+        alice.expect("m alice hi bob").await;
+        bob.expect("m alice hi bob").await;
+
+        bob.send("p alice just us").await;
+        alice.expect("pm bob_smith just us").await;
+
+        bob.send("n robert").await;
+        alice.expect("r bob_smith robert").await;
+        bob.expect("r bob_smith robert").await;
+
+        drop(bob);
+        alice.expect("d robert").await;
+
+        let mut carol = TestClient::connect(&users, "carol").await;
+        carol.expect("u alice").await;
+        alice.expect("c carol").await;
+
+        // Leaving cleanly is seen even while the connection is still open
+        carol.send("quit").await;
+        alice.expect("d carol").await;
+    }
+
+    #[tokio::test]
+    async fn rooms_only_reach_their_members() {
+        let users = SharedUsers::default();
+
+        let mut alice = TestClient::connect(&users, "alice").await;
+        alice.expect("u ").await;
+        alice.expect("c alice").await;
+        let mut bob = TestClient::connect(&users, "bob").await;
+        bob.expect("u alice").await;
+        bob.expect("c bob").await;
+        alice.expect("c bob").await;
+
+        alice.send("j rust").await;
+        alice.expect("ru rust").await;
+        alice.expect("j rust alice").await;
+
+        bob.send("rm rust not a member").await;
+        bob.send("rooms").await;
+        bob.expect("rl rust").await;
+
+        bob.send("j rust").await;
+        bob.expect("ru rust alice").await;
+        bob.expect("j rust bob").await;
+        alice.expect("j rust bob").await;
+
+        alice.send("rm rust hi all").await;
+        alice.expect("rm rust alice hi all").await;
+        bob.expect("rm rust alice hi all").await;
+
+        alice.send("l rust").await;
+        alice.expect("l rust alice").await;
+        bob.expect("l rust alice").await;
+
+        // Alice is no longer told about the room
+        bob.send("rm rust anyone here").await;
+        bob.expect("rm rust bob anyone here").await;
+        alice.send("m still here").await;
+        alice.expect("m alice still here").await;
+    }
+
+    #[tokio::test]
+    async fn rooms_are_found_by_their_cleaned_name() {
+        let users = SharedUsers::default();
+
+        let mut alice = TestClient::connect(&users, "alice").await;
+        alice.expect("u ").await;
+        alice.expect("c alice").await;
+
+        alice.send("j rust lang").await;
+        alice.expect("ru rust_lang").await;
+        alice.expect("j rust_lang alice").await;
+
+        alice.send("rm rust\tlang hi").await;
+        alice.expect("rm rust_lang alice hi").await;
+        alice.send("l rust  lang").await;
+        alice.expect("l rust_lang alice").await;
+    }
+}
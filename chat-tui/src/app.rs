@@ -0,0 +1,405 @@
+use crate::input::LineEditor;
+
+use chat_core::{
+    net::{client::Waker, connection::ConnectionData, error::NetError},
+    notify::{self, Notifier, NotifySettings},
+    session::{Session, View},
+    slash::{self, Command, Spec},
+    storage::{Storage, CONNECTIONS_KEY, NOTIFY_KEY, TIME_FORMAT_KEY},
+    timestamp::TimeFormat,
+};
+
+use ratatui::crossterm::event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+
+/// How many older messages are read back from history at a time.
+const READ_BACK: usize = 200;
+
+/// Rows scrolled by a page key.
+const PAGE: usize = 10;
+
+/// The server being added or changed, as a form of fields.
+pub struct ServerForm {
+    /// The tab being changed, or `None` for a new server.
+    pub tab: Option<usize>,
+    pub address: String,
+    pub name: String,
+    pub tls: bool,
+    pub reconnect: bool,
+    pub focus: Field,
+    editor: LineEditor,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Field {
+    Address,
+    Name,
+    Tls,
+    Reconnect,
+}
+
+impl Field {
+    pub const ALL: [Field; 4] = [Field::Address, Field::Name, Field::Tls, Field::Reconnect];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Field::Address => "Server address",
+            Field::Name => "Username",
+            Field::Tls => "Use TLS",
+            Field::Reconnect => "Reconnect automatically",
+        }
+    }
+
+    fn step(self, by: isize) -> Self {
+        let i = Self::ALL.iter().position(|&field| field == self).unwrap();
+        Self::ALL[(i as isize + by).rem_euclid(Self::ALL.len() as isize) as usize]
+    }
+}
+
+impl ServerForm {
+    fn new(tab: Option<usize>, connection: &ConnectionData) -> Self {
+        let mut editor = LineEditor::default();
+        editor.end();
+
+        Self {
+            tab,
+            address: connection.server().to_string(),
+            name: connection.name().to_string(),
+            tls: connection.tls(),
+            reconnect: connection.reconnect().enabled,
+            focus: Field::Address,
+            editor,
+        }
+    }
+
+    /// The text being edited and where its cursor is, if a text field has
+    /// focus.
+    pub fn cursor(&self) -> Option<(Field, usize)> {
+        match self.focus {
+            Field::Address => Some((Field::Address, self.editor.cursor(&self.address))),
+            Field::Name => Some((Field::Name, self.editor.cursor(&self.name))),
+            _ => None,
+        }
+    }
+
+    fn focus(&mut self, by: isize) {
+        self.focus = self.focus.step(by);
+        self.editor.end();
+    }
+}
+
+/// The terminal front end: every server's session, the chat shown and
+/// whatever is being edited.
+pub struct App {
+    pub tabs: Vec<Session>,
+    pub current_tab: usize,
+    pub composer: LineEditor,
+    pub server_form: Option<ServerForm>,
+    /// The commands listed over the rest of the UI, until a key is pressed.
+    pub help: Option<&'static [Spec]>,
+
+    /// Rows scrolled up from the newest message.
+    pub scroll: usize,
+    /// Most rows the message view could be scrolled up as last drawn.
+    pub max_scroll: usize,
+    /// The chat shown last time, as `(tab, view)`.
+    last_shown: Option<(usize, View)>,
+
+    pub time_format: TimeFormat,
+    notify: NotifySettings,
+    notifier: Box<dyn Notifier>,
+
+    storage: Storage,
+    waker: Waker,
+    pub quit: bool,
+}
+
+impl App {
+    /// Connects to every server saved in `storage`, calling `waker` whenever
+    /// one has something new to show.
+    pub fn new(storage: Storage, waker: Waker) -> Self {
+        let mut connections: Vec<ConnectionData> = storage.get(CONNECTIONS_KEY).unwrap_or_default();
+        if connections.is_empty() {
+            connections.push(ConnectionData::default());
+        }
+
+        let tabs = connections
+            .into_iter()
+            .map(|c| Session::new(c, waker.clone()))
+            .collect();
+
+        Self {
+            tabs,
+            current_tab: 0,
+            composer: LineEditor::default(),
+            server_form: None,
+            help: None,
+            scroll: 0,
+            max_scroll: 0,
+            last_shown: None,
+            time_format: storage.get(TIME_FORMAT_KEY).unwrap_or_default(),
+            notify: storage.get(NOTIFY_KEY).unwrap_or_default(),
+            notifier: notify::desktop(),
+            storage,
+            waker,
+            quit: false,
+        }
+    }
+
+    /// Saves the servers for next time, alongside the GUI's settings.
+    pub fn save(&mut self) {
+        let connections = self
+            .tabs
+            .iter()
+            .map(|tab| &tab.connection)
+            .collect::<Vec<_>>();
+        self.storage.set(CONNECTIONS_KEY, &connections);
+
+        if let Err(e) = self.storage.save() {
+            tracing::warn!("Couldn't save settings: {}", e);
+        }
+    }
+
+    /// Takes in what the network tasks have received, and marks the chat
+    /// shown as read.
+    pub fn sync(&mut self) {
+        for tab in self.tabs.iter_mut() {
+            tab.sync_messages(&self.notify, &mut *self.notifier);
+        }
+
+        let tab = &mut self.tabs[self.current_tab];
+        let shown = Some((self.current_tab, tab.view.clone()));
+        let returned = self.last_shown != shown;
+        if returned {
+            self.scroll = 0;
+            self.composer.end();
+            tab.completion = None;
+        }
+        self.last_shown = shown;
+
+        tab.current_chat().mark_read(returned);
+    }
+
+    pub fn tab(&mut self) -> &mut Session {
+        &mut self.tabs[self.current_tab]
+    }
+
+    /// Every chat in the order they are listed, as `(tab, view)`.
+    pub fn chats(&self) -> Vec<(usize, View)> {
+        let mut chats = Vec::new();
+
+        for (i, tab) in self.tabs.iter().enumerate() {
+            chats.push((i, View::Server));
+            chats.extend(tab.rooms.keys().map(|room| (i, View::Room(room.clone()))));
+            chats.extend((0..tab.conversations.len()).map(|j| (i, View::Conversation(j))));
+        }
+
+        chats
+    }
+
+    /// Shows the chat `by` places along the list from the current one.
+    fn step_chat(&mut self, by: isize) {
+        let chats = self.chats();
+        let current = chats
+            .iter()
+            .position(|(i, view)| *i == self.current_tab && *view == self.tabs[*i].view)
+            .unwrap_or(0);
+
+        let (i, view) =
+            chats[(current as isize + by).rem_euclid(chats.len() as isize) as usize].clone();
+        self.current_tab = i;
+        self.tabs[i].view = view;
+    }
+
+    pub fn handle(&mut self, event: Event) {
+        if let Event::Key(key) = event {
+            if key.kind == KeyEventKind::Press {
+                if self.help.is_some() {
+                    self.help = None;
+                } else if self.server_form.is_some() {
+                    self.form_key(key);
+                } else {
+                    self.key(key);
+                }
+            }
+        }
+    }
+
+    fn key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+        let alt = key.modifiers.contains(KeyModifiers::ALT);
+
+        match key.code {
+            KeyCode::Char('c' | 'q') if ctrl => self.quit = true,
+
+            KeyCode::Char('n') if ctrl => self.step_chat(1),
+            KeyCode::Down if alt => self.step_chat(1),
+            KeyCode::Char('p') if ctrl => self.step_chat(-1),
+            KeyCode::Up if alt => self.step_chat(-1),
+
+            KeyCode::Char('a') if ctrl => {
+                self.server_form = Some(ServerForm::new(None, &ConnectionData::default()));
+            }
+            KeyCode::Char('e') if ctrl => {
+                let connection = &self.tabs[self.current_tab].connection;
+                self.server_form = Some(ServerForm::new(Some(self.current_tab), connection));
+            }
+            KeyCode::Char('r') if ctrl => self.tab().reconnect(),
+            KeyCode::Char('w') if ctrl => self.close(),
+            KeyCode::Char('t') if ctrl => {
+                let tab = self.tab();
+                if let Some(NetError::UntrustedCertificate(fingerprint)) = tab.error.clone() {
+                    tab.connection.set_fingerprint(Some(fingerprint));
+                    tab.reconnect();
+                    self.save();
+                }
+            }
+
+            KeyCode::PageUp => {
+                self.scroll = (self.scroll + PAGE).min(self.max_scroll);
+
+                // Read back older messages once at the top, which keeps the
+                // same rows in view as they are counted from the bottom
+                if self.scroll == self.max_scroll {
+                    self.tab().current_chat().read_back(READ_BACK);
+                }
+            }
+            KeyCode::PageDown => {
+                self.scroll = self.scroll.saturating_sub(PAGE);
+                if self.scroll == 0 {
+                    self.tab().current_chat().forget_read_back();
+                }
+            }
+
+            KeyCode::Enter => {
+                let tab = &mut self.tabs[self.current_tab];
+                if !tab.current_chat().message.is_empty() {
+                    let command = tab.send_message();
+                    self.run(command);
+                    self.scroll = 0;
+                }
+            }
+
+            // Complete the only command matching what's typed, or else cycle
+            // through the names matching the word before the cursor
+            KeyCode::Tab => {
+                let tab = &mut self.tabs[self.current_tab];
+                let cursor = self.composer.cursor(&tab.current_chat().message);
+
+                if let Some(cursor) = tab.complete(cursor) {
+                    self.composer.move_to(cursor);
+                }
+            }
+
+            _ => {
+                let tab = &mut self.tabs[self.current_tab];
+                if self.composer.handle(&mut tab.current_chat().message, &key) {
+                    tab.command_error = None;
+                }
+            }
+        }
+    }
+
+    /// Runs a command the current tab's session left to the front end.
+    fn run(&mut self, command: Option<Command>) {
+        match command {
+            Some(Command::Help(topic)) => {
+                self.help = Some(topic.map_or(slash::COMMANDS, std::slice::from_ref));
+            }
+            Some(Command::Close) if self.tabs.len() > 1 => self.close(),
+            Some(Command::Close) => {
+                self.tab().command_error = Some("The only server can't be closed".to_string());
+            }
+            _ => (),
+        }
+    }
+
+    /// Leaves the room or conversation shown, or closes the server if it's
+    /// the server chat and there's another to show instead.
+    fn close(&mut self) {
+        let tab = self.tab();
+        match tab.view.clone() {
+            View::Room(name) => tab.leave_room(&name),
+            View::Conversation(i) => tab.close_conversation(i),
+            View::Server if self.tabs.len() > 1 => {
+                self.tabs.remove(self.current_tab);
+                self.current_tab = self.current_tab.min(self.tabs.len() - 1);
+                self.save();
+            }
+            View::Server => (),
+        }
+    }
+
+    fn form_key(&mut self, key: KeyEvent) {
+        let form = match &mut self.server_form {
+            Some(form) => form,
+            None => return,
+        };
+
+        match key.code {
+            KeyCode::Esc => self.server_form = None,
+            KeyCode::Enter => {
+                let form = self.server_form.take().unwrap();
+                self.apply(form);
+            }
+            KeyCode::Tab | KeyCode::Down => form.focus(1),
+            KeyCode::BackTab | KeyCode::Up => form.focus(-1),
+
+            _ => match form.focus {
+                Field::Address => {
+                    form.editor.handle(&mut form.address, &key);
+                }
+                Field::Name => {
+                    form.editor.handle(&mut form.name, &key);
+                }
+                Field::Tls if key.code == KeyCode::Char(' ') => form.tls = !form.tls,
+                Field::Reconnect if key.code == KeyCode::Char(' ') => {
+                    form.reconnect = !form.reconnect
+                }
+                _ => (),
+            },
+        }
+    }
+
+    /// Adds the server in `form`, or changes the one it was opened for.
+    fn apply(&mut self, form: ServerForm) {
+        // Only some of the details are in the form, the rest are kept
+        let edit = |connection: &mut ConnectionData| {
+            connection.set_server(&form.address);
+            connection.set_name(&form.name);
+            connection.set_tls(form.tls);
+
+            let mut reconnect = connection.reconnect().clone();
+            reconnect.enabled = form.reconnect;
+            connection.set_reconnect(reconnect);
+        };
+
+        match form.tab {
+            None => {
+                let mut connection = ConnectionData::default();
+                edit(&mut connection);
+
+                self.tabs.push(Session::new(connection, self.waker.clone()));
+                self.current_tab = self.tabs.len() - 1;
+            }
+
+            Some(i) => self.tabs[i].edit_connection(edit),
+        }
+
+        self.save();
+    }
+
+    /// What went wrong with the current server, and how to fix it if the
+    /// user can.
+    pub fn problem(&self) -> Option<String> {
+        let tab = &self.tabs[self.current_tab];
+
+        match &tab.error {
+            Some(NetError::UntrustedCertificate(fingerprint)) => Some(format!(
+                "Untrusted certificate {}, ^T to trust it if the server's owner gave the same",
+                fingerprint
+            )),
+            Some(e) => Some(e.to_string()),
+            None => tab.notice.clone(),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Edits a line of text in place, keeping track of the cursor.
+///
+/// The text itself is kept elsewhere, so the cursor is clamped to it in case
+/// it was changed behind the editor's back.
+#[derive(Default, Debug)]
+pub struct LineEditor {
+    /// Characters before the cursor.
+    cursor: usize,
+}
+
+impl LineEditor {
+    /// Where the cursor is in `text`, in characters.
+    pub fn cursor(&self, text: &str) -> usize {
+        self.cursor.min(text.chars().count())
+    }
+
+    /// Moves the cursor after the last character.
+    pub fn end(&mut self) {
+        self.cursor = usize::MAX;
+    }
+
+    /// Moves the cursor this many characters in.
+    pub fn move_to(&mut self, cursor: usize) {
+        self.cursor = cursor;
+    }
+
+    /// Applies `key` to `text`, returning whether it was an editing key.
+    pub fn handle(&mut self, text: &mut String, key: &KeyEvent) -> bool {
+        let len = text.chars().count();
+        let cursor = self.cursor(text);
+
+        match key.code {
+            KeyCode::Char(c)
+                if !key
+                    .modifiers
+                    .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+            {
+                text.insert(byte(text, cursor), c);
+                self.cursor = cursor + 1;
+            }
+            KeyCode::Backspace if cursor > 0 => {
+                text.remove(byte(text, cursor - 1));
+                self.cursor = cursor - 1;
+            }
+            KeyCode::Delete if cursor < len => {
+                text.remove(byte(text, cursor));
+            }
+            KeyCode::Left => self.cursor = cursor.saturating_sub(1),
+            KeyCode::Right => self.cursor = (cursor + 1).min(len),
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = len,
+            KeyCode::Backspace | KeyCode::Delete => (),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+/// Where the character `chars` characters into `text` starts.
+fn byte(text: &str, chars: usize) -> usize {
+    text.char_indices()
+        .nth(chars)
+        .map_or(text.len(), |(byte, _)| byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn type_keys(editor: &mut LineEditor, text: &mut String, keys: &[KeyCode]) {
+        for &code in keys {
+            assert!(editor.handle(text, &KeyEvent::from(code)));
+        }
+    }
+
+    #[test]
+    fn edits_at_the_cursor() {
+        let mut editor = LineEditor::default();
+        let mut text = String::new();
+
+        type_keys(
+            &mut editor,
+            &mut text,
+            &[
+                KeyCode::Char('h'),
+                KeyCode::Char('é'),
+                KeyCode::Char('o'),
+                KeyCode::Left,
+                KeyCode::Char('l'),
+                KeyCode::Char('l'),
+            ],
+        );
+        assert_eq!(text, "héllo");
+        assert_eq!(editor.cursor(&text), 4);
+
+        type_keys(
+            &mut editor,
+            &mut text,
+            &[
+                KeyCode::Home,
+                KeyCode::Delete,
+                KeyCode::End,
+                KeyCode::Backspace,
+            ],
+        );
+        assert_eq!(text, "éll");
+        assert_eq!(editor.cursor(&text), 3);
+
+        // Nothing to remove past either end
+        type_keys(&mut editor, &mut text, &[KeyCode::Delete, KeyCode::Right]);
+        assert_eq!(text, "éll");
+    }
+
+    #[test]
+    fn cursor_stays_in_changed_text() {
+        let mut editor = LineEditor::default();
+        let mut text = "hello".to_string();
+        editor.end();
+        assert_eq!(editor.cursor(&text), 5);
+
+        text.clear();
+        assert_eq!(editor.cursor(&text), 0);
+        type_keys(&mut editor, &mut text, &[KeyCode::Char('a')]);
+        assert_eq!(text, "a");
+
+        // Shortcuts are left for the app
+        let key = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL);
+        assert!(!editor.handle(&mut text, &key));
+        assert!(!editor.handle(&mut text, &KeyEvent::from(KeyCode::Enter)));
+    }
+}
@@ -0,0 +1,79 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+//! The chat client in a terminal, for wherever the GUI can't run. Servers
+//! are shared with the GUI, so either sees what was set up in the other.
+
+mod app;
+mod input;
+mod ui;
+
+use app::App;
+use chat_core::{net::client::Waker, storage::Storage};
+
+use ratatui::{crossterm::event, DefaultTerminal};
+use std::{io, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, Notify},
+    time,
+};
+
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    // Nothing is logged, as anything written would end up over the UI
+
+    let storage = Storage::open().unwrap_or_else(|_| Storage::default());
+    let woken = Arc::new(Notify::new());
+    let waker = {
+        let woken = woken.clone();
+        Waker::new(move || woken.notify_one())
+    };
+    let mut app = App::new(storage, waker);
+
+    // Reading the terminal blocks, so it gets a thread of its own
+    let (event_send, mut events) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while let Ok(event) = event::read() {
+            if event_send.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut terminal = ratatui::init();
+    let result = run(&mut terminal, &mut app, &woken, &mut events).await;
+    ratatui::restore();
+
+    app.save();
+    result
+}
+
+async fn run(
+    terminal: &mut DefaultTerminal,
+    app: &mut App,
+    woken: &Notify,
+    events: &mut mpsc::UnboundedReceiver<event::Event>,
+) -> io::Result<()> {
+    // Redraw every second anyway, for reconnect countdowns and message times
+    let mut ticks = time::interval(Duration::from_secs(1));
+
+    while !app.quit {
+        app.sync();
+        terminal.draw(|frame| ui::draw(frame, app))?;
+
+        tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => app.handle(event),
+                None => break,
+            },
+            _ = woken.notified() => (),
+            _ = ticks.tick() => (),
+        }
+
+        // Handle everything typed or pasted before drawing again
+        while let Ok(event) = events.try_recv() {
+            app.handle(event);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,425 @@
+use crate::app::{App, Field, ServerForm};
+
+use chat_core::{
+    complete::Completion,
+    net::commands::{ChatCommands, ConnectState},
+    session::{request_text, Chat, Session, View},
+    slash::{self, Spec},
+    timestamp,
+};
+
+use ratatui::{
+    layout::{Constraint, Layout, Position, Rect},
+    style::{Modifier, Style, Stylize},
+    text::{Line, Span, Text},
+    widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap},
+    Frame,
+};
+use std::time::SystemTime;
+
+/// Width of the list of servers and their chats.
+const SIDEBAR_WIDTH: u16 = 26;
+
+const KEYS: &str =
+    "^N/^P chats  PgUp/PgDn scroll  ^A add server  ^E edit  ^R reconnect  ^W close  ^Q quit";
+
+pub fn draw(frame: &mut Frame<'_>, app: &mut App) {
+    let [sidebar, main] =
+        Layout::horizontal([Constraint::Length(SIDEBAR_WIDTH), Constraint::Min(20)])
+            .areas(frame.area());
+    let [messages, composer, status] = Layout::vertical([
+        Constraint::Min(3),
+        Constraint::Length(3),
+        Constraint::Length(1),
+    ])
+    .areas(main);
+
+    chat_list(frame, app, sidebar);
+    message_view(frame, app, messages);
+    status_bar(frame, app, status);
+
+    let tab = &mut app.tabs[app.current_tab];
+    let hint = match &tab.view {
+        View::Server => "Enter message...".to_string(),
+        View::Room(name) => format!("Message #{}...", name),
+        View::Conversation(i) => format!("Message {}...", tab.conversations[*i].peer),
+    };
+    let error = tab.command_error.clone();
+    let message = &tab.current_chat().message.clone();
+    let cursor = app.composer.cursor(message);
+
+    // Problems with a command are shown right where it was typed
+    let mut block = Block::bordered();
+    if let Some(error) = error {
+        block = block.title(error.light_red());
+    }
+    let inner = block.inner(composer);
+    let text = if message.is_empty() {
+        Line::from(hint.dark_gray())
+    } else {
+        Line::from(message.as_str())
+    };
+
+    // Keep the cursor in view on long messages
+    let before = message.chars().take(cursor).collect::<String>();
+    let column = Line::from(before).width() as u16;
+    let offset = column.saturating_sub(inner.width.saturating_sub(1));
+    frame.render_widget(
+        Paragraph::new(text).scroll((0, offset)).block(block),
+        composer,
+    );
+
+    let commands = slash::matching(message);
+    let completion = tab
+        .completion
+        .as_ref()
+        .filter(|completion| completion.is_current(message))
+        .filter(|completion| completion.candidates().len() > 1);
+
+    if !commands.is_empty() {
+        command_list(frame, &commands, composer);
+    } else if let Some(completion) = completion {
+        name_list(frame, completion, composer);
+    }
+
+    match (&app.server_form, app.help) {
+        (Some(form), _) => server_form(frame, form),
+        (None, Some(commands)) => help(frame, commands),
+        (None, None) => {
+            frame.set_cursor_position(Position::new(inner.x + column - offset, inner.y))
+        }
+    }
+}
+
+/// Commands matching what's being typed, just above the `composer`.
+fn command_list(frame: &mut Frame<'_>, commands: &[&Spec], composer: Rect) {
+    let lines = commands
+        .iter()
+        .map(|spec| {
+            Line::from(vec![
+                spec.usage().bold(),
+                format!("  {}", spec.about).dark_gray(),
+            ])
+        })
+        .collect::<Vec<_>>();
+
+    let width = lines.iter().map(Line::width).max().unwrap_or(0) as u16 + 2;
+    let height = (lines.len() as u16 + 2).min(composer.y);
+    let area = Rect {
+        x: composer.x,
+        y: composer.y - height,
+        width: width.min(composer.width),
+        height,
+    };
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines).block(Block::bordered().title("Tab to complete")),
+        area,
+    );
+}
+
+/// Names the word being completed could be, just above the `composer`.
+fn name_list(frame: &mut Frame<'_>, completion: &Completion, composer: Rect) {
+    let mut line = Line::default();
+    for (i, name) in completion.candidates().iter().enumerate() {
+        let name = Span::from(name.clone());
+        line.push_span(if completion.selected() == Some(i) {
+            name.reversed()
+        } else {
+            name
+        });
+        line.push_span(" ");
+    }
+
+    let width = (line.width() as u16 + 2).min(composer.width);
+    let height = 3.min(composer.y);
+    let area = Rect {
+        x: composer.x,
+        y: composer.y - height,
+        width,
+        height,
+    };
+
+    frame.render_widget(Clear, area);
+    frame.render_widget(Paragraph::new(line).block(Block::bordered()), area);
+}
+
+/// Every command, or the one asked about, over the rest of the UI.
+fn help(frame: &mut Frame<'_>, commands: &[Spec]) {
+    let mut lines = commands
+        .iter()
+        .map(|spec| {
+            Line::from(vec![
+                format!("{:<28}", spec.usage()).bold(),
+                spec.about.into(),
+            ])
+        })
+        .collect::<Vec<_>>();
+    lines.push(Line::default());
+    lines.push(
+        "Other commands are sent to the server as typed."
+            .dark_gray()
+            .into(),
+    );
+    lines.push(
+        "Start a message with // to send it with a /."
+            .dark_gray()
+            .into(),
+    );
+
+    let area = frame.area().centered(
+        Constraint::Length(90),
+        Constraint::Length(lines.len() as u16 + 2),
+    );
+    frame.render_widget(Clear, area);
+    frame.render_widget(
+        Paragraph::new(lines)
+            .wrap(Wrap { trim: false })
+            .block(Block::bordered().title("Commands · any key to close")),
+        area,
+    );
+}
+
+/// Lists every server with its rooms and conversations under it.
+fn chat_list(frame: &mut Frame<'_>, app: &App, area: Rect) {
+    let mut items = Vec::new();
+    let mut selected = None;
+
+    for (i, tab) in app.tabs.iter().enumerate() {
+        let style = match tab.connect_state {
+            ConnectState::Connected => Style::new(),
+            ConnectState::Loading | ConnectState::Reconnecting { .. } => Style::new().yellow(),
+            ConnectState::Disconnect | ConnectState::Failed => Style::new().red(),
+        };
+
+        let mut line = Line::from(Span::styled(tab.connection.server().clone(), style));
+        let (unread, mentions) = tab.unread();
+        if mentions > 0 {
+            line.push_span(format!(" @{}", mentions).light_red().bold());
+        }
+        if unread > 0 {
+            line.push_span(format!(" {}", unread).bold());
+        }
+        if let ConnectState::Reconnecting { attempt, next_in } = tab.connect_state {
+            line.push_span(
+                format!(" #{} in {}s", attempt, next_in.as_secs_f32().ceil()).dark_gray(),
+            );
+        }
+
+        let mut push = |view: View, line: Line<'static>| {
+            if i == app.current_tab && tab.view == view {
+                selected = Some(items.len());
+            }
+            items.push(ListItem::new(line));
+        };
+
+        push(View::Server, line);
+        for (name, room) in &tab.rooms {
+            push(
+                View::Room(name.clone()),
+                chat_label(format!("  #{}", name), &room.chat),
+            );
+        }
+        for (j, conversation) in tab.conversations.iter().enumerate() {
+            push(
+                View::Conversation(j),
+                chat_label(format!("  {}", conversation.peer), &conversation.chat),
+            );
+        }
+    }
+
+    let list = List::new(items)
+        .block(Block::bordered().title("Servers"))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    let mut state = ListState::default().with_selected(selected);
+    frame.render_stateful_widget(list, area, &mut state);
+}
+
+/// A chat's name in the list, marked if there's anything new in it.
+fn chat_label(name: String, chat: &Chat) -> Line<'static> {
+    if chat.mentions() > 0 {
+        format!("{} ({})", name, chat.unread())
+            .light_red()
+            .bold()
+            .into()
+    } else if chat.unread() > 0 {
+        format!("{} ({})", name, chat.unread()).bold().into()
+    } else {
+        name.into()
+    }
+}
+
+fn title(tab: &Session) -> String {
+    match &tab.view {
+        View::Server => tab.connection.server().to_string(),
+        View::Room(name) => format!("#{}", name),
+        View::Conversation(i) => {
+            let peer = &tab.conversations[*i].peer;
+            if tab.connect_state == ConnectState::Connected && !tab.roster.contains(peer) {
+                format!("Direct messages with {} (offline)", peer)
+            } else {
+                format!("Direct messages with {}", peer)
+            }
+        }
+    }
+}
+
+/// The chat shown, scrolled `app.scroll` rows up from the newest message,
+/// followed by our pending messages.
+fn message_view(frame: &mut Frame<'_>, app: &mut App, area: Rect) {
+    let now = SystemTime::now();
+    let time_format = app.time_format;
+    let tab = &mut app.tabs[app.current_tab];
+
+    let block = Block::bordered().title(title(tab));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    let pending = tab.pending();
+    let name = tab.connection.name().clone();
+    let chat = tab.current_chat();
+    let messages = chat.messages();
+    let divider = chat
+        .divider()
+        .and_then(|row| row.checked_sub(chat.paged_out()));
+
+    let mut lines = Vec::new();
+    for (row, record) in messages.iter().enumerate() {
+        // Separate each day's messages
+        if row == 0 || !timestamp::same_day(messages[row - 1].when(), record.when()) {
+            lines.push(Line::from(timestamp::day_label(record.when()).dark_gray()).centered());
+        }
+
+        // Mark where we stopped reading
+        if divider == Some(row) {
+            lines.push(Line::from("New messages".light_red()).centered());
+        }
+
+        let time = format!("{} ", time_format.format(record.when(), now)).dark_gray();
+        let line = match &record.command {
+            ChatCommands::Message { sender, message }
+            | ChatCommands::PrivateMessage { sender, message }
+            | ChatCommands::RoomMessage {
+                sender, message, ..
+            } => match slash::action(message) {
+                Some(action) => {
+                    let action = Span::from(format!("* {} {}", sender, action)).italic();
+                    Line::from(vec![time, action])
+                }
+                None => {
+                    let sender = Span::from(format!("{}: ", sender)).bold();
+                    Line::from(vec![time, sender, message.clone().into()])
+                }
+            },
+
+            c @ (ChatCommands::UserConnected { .. } | ChatCommands::UserJoined { .. }) => {
+                Line::from(vec![time, "+ ".green(), c.text().bold()])
+            }
+
+            c @ (ChatCommands::UserDisconnected { .. } | ChatCommands::UserLeft { .. }) => {
+                Line::from(vec![time, "- ".red(), c.text().bold()])
+            }
+
+            c => Line::from(vec![time, "! ".green(), c.text().bold()]),
+        };
+        lines.push(line);
+    }
+
+    for (_, request) in &pending {
+        lines.push(Line::from(vec![
+            "pending ".dark_gray(),
+            format!("{}: ", name).dark_gray().bold(),
+            request_text(request).dark_gray().italic(),
+        ]));
+    }
+
+    let paragraph = Paragraph::new(Text::from(lines)).wrap(Wrap { trim: false });
+    let total = paragraph.line_count(inner.width);
+    app.max_scroll = total.saturating_sub(inner.height as usize);
+    app.scroll = app.scroll.min(app.max_scroll);
+
+    let top = (app.max_scroll - app.scroll).min(u16::MAX as usize) as u16;
+    frame.render_widget(paragraph.scroll((top, 0)), inner);
+}
+
+fn status_bar(frame: &mut Frame<'_>, app: &App, area: Rect) {
+    let tab = &app.tabs[app.current_tab];
+
+    let mut line = Line::from(match tab.connect_state {
+        ConnectState::Loading => "Connecting",
+        ConnectState::Connected => "Connected",
+        ConnectState::Disconnect => "Disconnected",
+        ConnectState::Failed => "Couldn't connect",
+        ConnectState::Reconnecting { .. } => "Reconnecting",
+    });
+    if let Some(latency) = tab.latency {
+        line.push_span(format!(" · Latency {} ms", latency.as_millis()));
+    }
+
+    match app.problem() {
+        Some(problem) => line.push_span(format!(" · {}", problem).yellow()),
+        None => line.push_span(format!(" · {}", KEYS).dark_gray()),
+    }
+
+    frame.render_widget(Paragraph::new(line), area);
+}
+
+/// The server being added or changed, over the rest of the UI.
+fn server_form(frame: &mut Frame<'_>, form: &ServerForm) {
+    let area = frame
+        .area()
+        .centered(Constraint::Length(50), Constraint::Length(8));
+    let block = Block::bordered().title(match form.tab {
+        Some(_) => "Server details",
+        None => "Add server",
+    });
+    let inner = block.inner(area);
+    frame.render_widget(Clear, area);
+    frame.render_widget(block, area);
+
+    let label_width = 26;
+    let rows = Layout::vertical(Field::ALL.map(|_| Constraint::Length(1))).split(inner);
+
+    for (field, &row) in Field::ALL.iter().zip(rows.iter()) {
+        let mut label = Span::raw(format!("{:<1$}", field.label(), label_width as usize));
+        if form.focus == *field {
+            label = label.reversed();
+        }
+
+        let value = match field {
+            Field::Address => form.address.clone(),
+            Field::Name => form.name.clone(),
+            Field::Tls => checkbox(form.tls),
+            Field::Reconnect => checkbox(form.reconnect),
+        };
+        frame.render_widget(Paragraph::new(Line::from(vec![label, value.into()])), row);
+    }
+
+    let keys = Rect {
+        y: inner.bottom().saturating_sub(1),
+        height: 1,
+        ..inner
+    };
+    frame.render_widget(
+        Paragraph::new("Tab next · Space toggle · Enter save · Esc cancel".dark_gray()),
+        keys,
+    );
+
+    if let Some((field, cursor)) = form.cursor() {
+        let i = Field::ALL.iter().position(|&f| f == field).unwrap();
+        let text = match field {
+            Field::Address => &form.address,
+            _ => &form.name,
+        };
+        let column = Line::from(text.chars().take(cursor).collect::<String>()).width() as u16;
+        frame.set_cursor_position(Position::new(
+            (rows[i].x + label_width + column).min(inner.right().saturating_sub(1)),
+            rows[i].y,
+        ));
+    }
+}
+
+fn checkbox(checked: bool) -> String {
+    if checked { "[x]" } else { "[ ]" }.to_string()
+}
@@ -1,133 +1,274 @@
-use crate::net::{client, commands::*, connection::ConnectionData};
+use crate::scrollback::RowHeights;
+use chat_core::{
+    history::Retention,
+    net::{
+        client::Waker,
+        commands::*,
+        connection::{ConnectionData, Keepalive, ReconnectPolicy},
+        error::NetError,
+    },
+    notify::{self, Notifier, NotifySettings},
+    search::{self, Query},
+    session::{record_line, request_text, room_name, Chat, Persistence, Session, View},
+    slash::{self, Command, Spec},
+    storage::{Storage, CONNECTIONS_KEY, NOTIFY_KEY, TIME_FORMAT_KEY},
+    timestamp::{self, TimeFormat},
+};
 
+use chrono::{DateTime, Local};
 use egui::vec2;
-use std::thread;
-use tokio::sync::mpsc::{self, error::TryRecvError};
+use serde::{de::DeserializeOwned, Serialize};
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime},
+};
 
-struct Tab {
-    messages: Vec<ChatCommands>,
-    send: mpsc::Sender<String>,
-    recv: mpsc::Receiver<ClientCommands>,
-    message: String,
+/// How many older messages are read back from history at a time.
+const READ_BACK: usize = 200;
 
-    connect_state: ConnectState,
+#[derive(PartialEq, Default)]
+enum ServerEdit {
+    #[default]
+    None,
+    New,
+    Change(usize),
+}
 
-    connection: ConnectionData,
+const SHOW_ROSTER_KEY: &str = "show_roster";
+
+/// A message found by the search panel, as it is listed.
+struct Hit {
+    tab: usize,
+    /// The chat it was found in.
+    view: View,
+    /// Counted from the oldest message in the history.
+    row: usize,
+    /// The message before, for context.
+    previous: Option<Record>,
+    record: Record,
 }
 
-impl Tab {
-    fn new(egui_ctx: egui::Context, connection: ConnectionData) -> Self {
-        let (tab_send, client_recv) = mpsc::channel::<String>(5);
-        let (client_send, tab_recv) = mpsc::channel::<ClientCommands>(100);
+/// State of the search panel.
+#[derive(Default)]
+struct Search {
+    open: bool,
+    focus: bool,
 
-        let thread_connection = connection.clone();
+    text: String,
+    sender: String,
+    from: String,
+    until: String,
+    all_tabs: bool,
 
-        tokio::spawn(async move {
-            client::network(client_send, client_recv, egui_ctx, thread_connection).await
-        });
+    /// Matching messages as `(tab, chat, row)`, with rows counted from the
+    /// oldest message in the chat's history.
+    hits: Vec<(usize, View, usize)>,
+    /// The first `MAX_SHOWN` hits, read back from history if they have to be.
+    shown: Vec<Hit>,
+    /// Tab the hits are from, unless searching all of them.
+    hits_tab: usize,
+    selected: Option<(usize, View, usize)>,
+    /// Scroll the message grid to the selected hit on the next frame.
+    scroll_to_selected: bool,
+}
 
-        Self {
-            messages: Vec::new(),
-            send: tab_send,
-            recv: tab_recv,
-            message: String::new(),
-            connect_state: ConnectState::Loading,
-            connection,
+impl Search {
+    /// Most hits listed at once.
+    const MAX_SHOWN: usize = 200;
+
+    fn query(&self) -> Query {
+        Query {
+            text: self.text.clone(),
+            sender: self.sender.clone(),
+            from: search::parse_date(&self.from),
+            until: search::parse_end_date(&self.until),
         }
     }
 
-    fn reconnect(&mut self, egui_ctx: egui::Context) {
-        let (tab_send, client_recv) = mpsc::channel::<String>(5);
-        let (client_send, tab_recv) = mpsc::channel::<ClientCommands>(100);
+    fn update_hits(&mut self, tabs: &[Session], current_tab: usize) {
+        let query = self.query();
+        self.hits.clear();
+        self.hits_tab = current_tab;
 
-        let thread_connection = self.connection.clone();
+        for (i, tab) in tabs.iter().enumerate() {
+            if self.all_tabs || i == current_tab {
+                for (view, chat) in tab.chats() {
+                    let rows = chat.search(&query);
+                    self.hits
+                        .extend(rows.into_iter().map(|row| (i, view.clone(), row)));
+                }
+            }
+        }
 
-        tokio::spawn(async move {
-            client::network(client_send, client_recv, egui_ctx, thread_connection).await
-        });
+        self.shown = self
+            .hits
+            .iter()
+            .take(Self::MAX_SHOWN)
+            .filter_map(|(tab, view, row)| {
+                let (tab, row) = (*tab, *row);
+                let chat = tabs[tab].chats().find(|(v, _)| v == view)?.1;
+                let mut records = chat.rows(row.saturating_sub(1)..row + 1);
+                let record = records.pop()?;
 
-        self.send = tab_send;
-        self.recv = tab_recv;
+                Some(Hit {
+                    tab,
+                    view: view.clone(),
+                    row,
+                    previous: records.pop(),
+                    record,
+                })
+            })
+            .collect();
     }
+}
 
-    fn change_name(&mut self, name: &str) {
-        let message = format!("/n {}", name);
+/// How a chat was last drawn, which the session doesn't need to know.
+#[derive(Default)]
+struct ChatView {
+    heights: RowHeights,
+    /// Messages the chat had paged out when last drawn.
+    paged_out: usize,
+    /// Scroll the message view here on the next frame.
+    scroll_to: Option<f32>,
+    /// Scroll this row into view on the next frame, counted from the oldest
+    /// message in the history.
+    scroll_to_row: Option<usize>,
+}
 
-        self.send(message);
+impl ChatView {
+    /// Keeps the row heights lined up with the messages in memory as older
+    /// ones are paged out or read back.
+    fn follow(&mut self, chat: &Chat) {
+        let paged_out = chat.paged_out();
 
-        self.connection.set_name(name);
+        if paged_out > self.paged_out {
+            self.heights.remove_front(paged_out - self.paged_out);
+        } else if paged_out < self.paged_out {
+            self.heights.insert_front(self.paged_out - paged_out);
+        }
+        self.paged_out = paged_out;
     }
+}
 
-    fn send_message(&mut self) {
-        let message = self.message.clone();
-        self.send(message);
-        self.message.clear();
-    }
+/// How the client was started, usually from the command line.
+#[derive(Default)]
+pub struct Options {
+    /// A server to open, in a tab of its own unless it's already saved.
+    pub server: Option<ConnectionData>,
+    /// Where settings, history and unsent messages are kept.
+    pub persistence: Persistence,
+}
 
-    fn send(&mut self, message: String) {
-        let sender = self.send.clone();
-        thread::spawn(move || {
-            sender.blocking_send(message).unwrap();
-        });
+/// Where settings are loaded from and saved to.
+#[derive(Default)]
+enum Settings {
+    /// With eframe's own state.
+    #[default]
+    Eframe,
+    /// In a directory given on the command line.
+    Dir(Storage),
+    Off,
+}
+
+impl Settings {
+    fn new(persistence: &Persistence) -> Self {
+        match persistence {
+            Persistence::Default => Self::Eframe,
+            Persistence::In(dir) => Self::Dir(Storage::open_in(dir).unwrap_or_else(|e| {
+                tracing::warn!("Couldn't open settings: {}", e);
+                Storage::default()
+            })),
+            Persistence::Off => Self::Off,
+        }
     }
 
-    fn sync_messages(&mut self) {
-        if self.connect_state != ConnectState::Failed
-            || self.connect_state != ConnectState::Disconnect
-        {
-            loop {
-                match self.recv.try_recv() {
-                    Ok(ClientCommands::ChatCommand(c)) => self.messages.push(c),
-                    Ok(ClientCommands::ConnectState(s)) => self.connect_state = s,
-
-                    Err(TryRecvError::Disconnected) => {
-                        self.connect_state = match self.connect_state {
-                            ConnectState::Connected => ConnectState::Disconnect,
-                            _ => ConnectState::Failed,
-                        };
-
-                        break;
-                    }
+    fn get<T: DeserializeOwned>(
+        &self,
+        eframe: Option<&dyn eframe::Storage>,
+        key: &str,
+    ) -> Option<T> {
+        match self {
+            Self::Eframe => eframe.and_then(|storage| eframe::get_value(storage, key)),
+            Self::Dir(storage) => storage.get(key),
+            Self::Off => None,
+        }
+    }
 
-                    Err(TryRecvError::Empty) => break,
-                }
-            }
+    fn set<T: Serialize>(&mut self, eframe: &mut dyn eframe::Storage, key: &str, value: &T) {
+        match self {
+            Self::Eframe => eframe::set_value(eframe, key, value),
+            Self::Dir(storage) => storage.set(key, value),
+            Self::Off => (),
         }
     }
 }
 
-#[derive(PartialEq, Default)]
-enum ServerEdit {
-    #[default]
-    None,
-    New,
-    Change(usize),
+/// Repaints whenever a session has something new to show.
+fn waker(ctx: &egui::Context) -> Waker {
+    let ctx = ctx.clone();
+    Waker::new(move || ctx.request_repaint())
 }
 
 #[derive(Default)]
 pub struct Client {
-    tabs: Vec<Tab>,
+    tabs: Vec<Session>,
 
     current_tab: usize,
 
     server_edit: ServerEdit,
     server_edit_name: String,
     server_edit_address: String,
+    server_edit_reconnect: ReconnectPolicy,
+    server_edit_keepalive: Keepalive,
+    server_edit_tls: bool,
+    server_edit_fingerprint: Option<String>,
+    /// The pinned certificate was forgotten in the form.
+    server_edit_forget_fingerprint: bool,
+    server_edit_retention: Retention,
+
+    rooms_open: bool,
+    join_room: String,
+    /// The commands listed in the help window, while it's open.
+    help: Option<&'static [Spec]>,
+
+    search: Search,
+    time_format: TimeFormat,
+    show_roster: bool,
+    /// Give the composer focus on the next frame.
+    focus_composer: bool,
+    /// The chat shown last frame, as `(tab, view)`.
+    last_shown: Option<(usize, View)>,
+    /// How each chat was last drawn, by the name of its history.
+    chat_views: HashMap<String, ChatView>,
+
+    notify: NotifySettings,
+    /// The keywords being edited, separated by commas.
+    notify_keywords: String,
+    notifier: Box<dyn Notifier>,
+
+    settings: Settings,
+    persistence: Persistence,
 }
 
 impl Client {
     /// Called once before the first frame.
-    pub fn new(cc: &eframe::CreationContext<'_>, server: Option<&str>) -> Self {
+    pub fn new(cc: &eframe::CreationContext<'_>, options: Options) -> Self {
+        let settings = Settings::new(&options.persistence);
+
         let mut start_tab = 0;
-        let mut connections = if let Some(storage) = cc.storage {
-            eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default()
-        } else {
-            Vec::new()
-        };
-
-        if let Some(server) = server {
-            connections.push(ConnectionData::new(server, "nobody"));
-            start_tab = connections.len() - 1;
+        let mut connections: Vec<ConnectionData> = settings
+            .get(cc.storage, CONNECTIONS_KEY)
+            .unwrap_or_default();
+
+        if let Some(server) = options.server {
+            let saved = connections
+                .iter()
+                .position(|c| c.server() == server.server() && c.name() == server.name());
+
+            start_tab = saved.unwrap_or_else(|| {
+                connections.push(server);
+                connections.len() - 1
+            });
         }
 
         if connections.is_empty() {
@@ -137,20 +278,311 @@ impl Client {
         // Start network thread
         let mut tabs = Vec::new();
         for c in connections {
-            let egui_ctx = cc.egui_ctx.clone();
-            tabs.push(Tab::new(egui_ctx, c));
+            tabs.push(Session::new_with(
+                options.persistence.clone(),
+                c,
+                waker(&cc.egui_ctx),
+            ));
         }
 
         // This is also where you can customized the look at feel of egui using
         // `cc.egui_ctx.set_visuals` and `cc.egui_ctx.set_fonts`.
 
+        let time_format = settings
+            .get(cc.storage, TIME_FORMAT_KEY)
+            .unwrap_or_default();
+        let show_roster = settings.get(cc.storage, SHOW_ROSTER_KEY).unwrap_or(true);
+        let notify: NotifySettings = settings.get(cc.storage, NOTIFY_KEY).unwrap_or_default();
+
         Self {
             tabs,
             current_tab: start_tab,
+            time_format,
+            show_roster,
+            notify_keywords: notify.keywords.join(", "),
+            notify,
+            notifier: notify::desktop(),
+            settings,
+            persistence: options.persistence,
 
             ..Default::default()
         }
     }
+
+    fn search_panel(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::right("search_panel").show(ctx, |ui| {
+            let search = &mut self.search;
+            let mut changed = false;
+
+            ui.horizontal(|ui| {
+                ui.heading("Search");
+                ui.with_layout(egui::Layout::right_to_left(), |ui| {
+                    if ui.small_button("❌").clicked() {
+                        search.open = false;
+                    }
+                });
+            });
+
+            egui::Grid::new("search_grid")
+                .num_columns(2)
+                .show(ui, |ui| {
+                    ui.label("Text");
+                    let response = ui.text_edit_singleline(&mut search.text);
+                    if search.focus {
+                        response.request_focus();
+                        search.focus = false;
+                    }
+                    changed |= response.changed();
+                    ui.end_row();
+
+                    ui.label("Sender");
+                    changed |= ui.text_edit_singleline(&mut search.sender).changed();
+                    ui.end_row();
+
+                    ui.label("From");
+                    changed |= ui
+                        .add(egui::TextEdit::singleline(&mut search.from).hint_text("YYYY-MM-DD"))
+                        .changed();
+                    ui.end_row();
+
+                    ui.label("Until");
+                    changed |= ui
+                        .add(egui::TextEdit::singleline(&mut search.until).hint_text("YYYY-MM-DD"))
+                        .changed();
+                    ui.end_row();
+                });
+
+            changed |= ui.checkbox(&mut search.all_tabs, "All servers").changed();
+
+            if changed || (!search.all_tabs && search.hits_tab != self.current_tab) {
+                search.update_hits(&self.tabs, self.current_tab);
+            }
+
+            ui.separator();
+            ui.label(format!("{} found", search.hits.len()));
+
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for hit in &search.shown {
+                        let (tab, row, record) = (hit.tab, hit.row, &hit.record);
+                        if tab >= self.tabs.len() {
+                            continue;
+                        }
+
+                        let time = DateTime::<Local>::from(record.when()).format("%Y-%m-%d %H:%M");
+                        let mut header = time.to_string();
+                        match &hit.view {
+                            View::Server => (),
+                            View::Room(name) => header = format!("#{} · {}", name, header),
+                            View::Conversation(i) => {
+                                if let Some(conversation) = self.tabs[tab].conversations.get(*i) {
+                                    header = format!("{} · {}", conversation.peer, header);
+                                }
+                            }
+                        }
+                        if search.all_tabs {
+                            header = format!("{} · {}", self.tabs[tab].connection.server(), header);
+                        }
+                        ui.label(egui::RichText::new(header).small().weak());
+
+                        if let Some(previous) = &hit.previous {
+                            ui.label(egui::RichText::new(record_line(previous)).small().weak());
+                        }
+
+                        // The chat may have been left or closed since
+                        let found_in = self.tabs[tab].chats().any(|(view, _)| view == hit.view);
+
+                        let hit_key = (tab, hit.view.clone(), row);
+                        let selected = search.selected.as_ref() == Some(&hit_key);
+                        if ui.selectable_label(selected, record_line(record)).clicked() && found_in
+                        {
+                            search.selected = Some(hit_key);
+                            search.scroll_to_selected = true;
+                            self.current_tab = tab;
+                            self.tabs[tab].view = hit.view.clone();
+                        }
+
+                        ui.separator();
+                    }
+
+                    if search.hits.len() > Search::MAX_SHOWN {
+                        ui.weak(format!(
+                            "{} more, narrow the search to see them",
+                            search.hits.len() - Search::MAX_SHOWN
+                        ));
+                    }
+                });
+        });
+    }
+
+    fn roster_panel(&mut self, ctx: &egui::Context) {
+        egui::SidePanel::right("roster_panel").show(ctx, |ui| {
+            let tab = &mut self.tabs[self.current_tab];
+            let (title, roster) = match &tab.view {
+                View::Room(room) => (format!("#{}", room), &tab.rooms[room].members),
+                _ => ("Online".to_string(), &tab.roster),
+            };
+
+            ui.heading(format!("{} ({})", title, roster.len()));
+
+            if tab.connect_state != ConnectState::Connected {
+                ui.weak("Not connected");
+                return;
+            }
+
+            let mut mention = None;
+            let mut message = None;
+            egui::ScrollArea::vertical()
+                .auto_shrink([false, false])
+                .show(ui, |ui| {
+                    for name in roster.users() {
+                        let mut text = egui::RichText::new(name);
+                        if name == tab.connection.name() {
+                            text = text.strong();
+                        }
+
+                        let response = ui
+                            .selectable_label(false, text)
+                            .on_hover_text("Mention")
+                            .context_menu(|ui| {
+                                if ui.button("Message privately").clicked() {
+                                    message = Some(name.to_string());
+                                    ui.close_menu();
+                                }
+                            });
+
+                        if response.clicked() {
+                            mention = Some(name.to_string());
+                        }
+                    }
+                });
+
+            if let Some(name) = message {
+                tab.view = View::Conversation(tab.conversation(&name));
+                self.focus_composer = true;
+            }
+
+            if let Some(name) = mention {
+                tab.mention(&name);
+                self.focus_composer = true;
+            }
+        });
+    }
+
+    /// Runs a command the current tab's session left to the client.
+    fn run(&mut self, command: Option<Command>) {
+        match command {
+            Some(Command::Help(topic)) => {
+                self.help = Some(topic.map_or(slash::COMMANDS, std::slice::from_ref));
+            }
+            Some(Command::Close) if self.tabs.len() > 1 => {
+                self.tabs.remove(self.current_tab);
+                self.current_tab = self.current_tab.min(self.tabs.len() - 1);
+            }
+            Some(Command::Close) => {
+                self.tabs[self.current_tab].command_error =
+                    Some("The only server can't be closed".to_string());
+            }
+            _ => (),
+        }
+    }
+
+    fn help_window(&mut self, ctx: &egui::Context) {
+        let mut open = true;
+
+        egui::Window::new("Commands")
+            .open(&mut open)
+            .resizable(false)
+            .show(ctx, |ui| {
+                egui::Grid::new("commands").striped(true).show(ui, |ui| {
+                    for spec in self.help.unwrap_or_default() {
+                        ui.monospace(spec.usage());
+                        ui.label(spec.about);
+                        ui.end_row();
+                    }
+                });
+
+                ui.weak("Other commands are sent to the server as typed.");
+                ui.weak("Start a message with // to send it with a /.");
+            });
+
+        if !open {
+            self.help = None;
+        }
+    }
+
+    fn rooms_window(&mut self, ctx: &egui::Context) {
+        let tab = &mut self.tabs[self.current_tab];
+        let mut join = None;
+
+        egui::Window::new("Rooms")
+            .open(&mut self.rooms_open)
+            .default_width(200.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let response = ui.add(
+                        egui::TextEdit::singleline(&mut self.join_room)
+                            .desired_width(120.0)
+                            .hint_text("room"),
+                    );
+                    let entered = response.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+
+                    let name = room_name(&self.join_room);
+                    if ui
+                        .add_enabled(!name.is_empty(), egui::Button::new("Join"))
+                        .clicked()
+                        || (entered && !name.is_empty())
+                    {
+                        join = Some(name);
+                        self.join_room.clear();
+                    }
+                });
+
+                ui.separator();
+
+                ui.horizontal(|ui| {
+                    ui.label("On this server");
+                    if ui.small_button("Refresh").clicked() {
+                        let _ = tab.send(ClientRequest::ListRooms);
+                    }
+                });
+
+                if tab.available_rooms.is_empty() {
+                    ui.weak("No rooms yet");
+                }
+
+                for name in &tab.available_rooms {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("#{}", name));
+
+                        if tab.rooms.contains_key(name) {
+                            ui.weak("joined");
+                        } else if ui.small_button("Join").clicked() {
+                            join = Some(name.clone());
+                        }
+                    });
+                }
+            });
+
+        if let Some(name) = join {
+            tab.join_room(&name);
+            tab.view = View::Room(name);
+            self.focus_composer = true;
+        }
+    }
+
+    fn close_server_edit(&mut self) {
+        self.server_edit_address.clear();
+        self.server_edit_name.clear();
+        self.server_edit_reconnect = ReconnectPolicy::default();
+        self.server_edit_keepalive = Keepalive::default();
+        self.server_edit_tls = false;
+        self.server_edit_fingerprint = None;
+        self.server_edit_forget_fingerprint = false;
+        self.server_edit_retention = Retention::default();
+        self.server_edit = ServerEdit::None;
+    }
 }
 
 impl eframe::App for Client {
@@ -162,7 +594,21 @@ impl eframe::App for Client {
             connections.push(&tab.connection);
         }
 
-        eframe::set_value(storage, eframe::APP_KEY, &connections);
+        let settings = &mut self.settings;
+        settings.set(storage, CONNECTIONS_KEY, &connections);
+        settings.set(storage, TIME_FORMAT_KEY, &self.time_format);
+        settings.set(storage, SHOW_ROSTER_KEY, &self.show_roster);
+        settings.set(storage, NOTIFY_KEY, &self.notify);
+
+        if let Settings::Dir(storage) = settings {
+            if let Err(e) = storage.save() {
+                tracing::warn!("Couldn't save settings: {}", e);
+            }
+        }
+    }
+
+    fn persist_egui_memory(&self) -> bool {
+        !matches!(self.settings, Settings::Off)
     }
 
     /// Called each time the UI needs repainting, which may be many times per second.
@@ -170,13 +616,71 @@ impl eframe::App for Client {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         // Update data
         for tab in self.tabs.iter_mut() {
-            tab.sync_messages();
+            tab.sync_messages(&self.notify, &mut *self.notifier);
+        }
+
+        if ctx
+            .input_mut()
+            .consume_key(egui::Modifiers::COMMAND, egui::Key::F)
+        {
+            self.search.open = !self.search.open;
+            self.search.focus = self.search.open;
         }
 
         egui::TopBottomPanel::top("top_panel").show(ctx, |ui| {
             egui::menu::bar(ui, |ui| {
                 egui::warn_if_debug_build(ui);
 
+                ui.menu_button("View", |ui| {
+                    ui.label("Message times");
+                    for format in TimeFormat::ALL {
+                        ui.radio_value(&mut self.time_format, format, format.name());
+                    }
+
+                    ui.separator();
+                    ui.checkbox(&mut self.show_roster, "Online users");
+                    if ui.button("Commands").clicked() {
+                        self.help = Some(slash::COMMANDS);
+                        ui.close_menu();
+                    }
+                });
+
+                ui.menu_button("Notifications", |ui| {
+                    ui.checkbox(&mut self.notify.do_not_disturb, "Do not disturb");
+
+                    let connection = &mut self.tabs[self.current_tab].connection;
+                    let mut muted = connection.muted();
+                    if ui
+                        .checkbox(&mut muted, format!("Mute {}", connection.server()))
+                        .changed()
+                    {
+                        connection.set_muted(muted);
+                    }
+
+                    ui.separator();
+                    ui.label("Besides mentions and direct messages, notify about");
+                    if ui
+                        .add(
+                            egui::TextEdit::singleline(&mut self.notify_keywords)
+                                .hint_text("Words, separated by commas"),
+                        )
+                        .changed()
+                    {
+                        self.notify.keywords = self
+                            .notify_keywords
+                            .split(',')
+                            .map(str::trim)
+                            .filter(|keyword| !keyword.is_empty())
+                            .map(str::to_string)
+                            .collect();
+                    }
+                });
+
+                if ui.button("Search").clicked() {
+                    self.search.open = !self.search.open;
+                    self.search.focus = self.search.open;
+                }
+
                 ui.menu_button("Server", |ui| {
                     if ui.button("Edit").clicked() {
                         self.server_edit = ServerEdit::Change(self.current_tab);
@@ -185,24 +689,74 @@ impl eframe::App for Client {
                             self.tabs[self.current_tab].connection.server().to_string();
                         self.server_edit_name =
                             self.tabs[self.current_tab].connection.name().to_string();
+                        self.server_edit_reconnect =
+                            self.tabs[self.current_tab].connection.reconnect().clone();
+                        self.server_edit_keepalive =
+                            self.tabs[self.current_tab].connection.keepalive().clone();
+                        self.server_edit_tls = self.tabs[self.current_tab].connection.tls();
+                        self.server_edit_fingerprint = self.tabs[self.current_tab]
+                            .connection
+                            .fingerprint()
+                            .map(str::to_string);
+                        self.server_edit_retention =
+                            self.tabs[self.current_tab].connection.retention().clone();
+                    }
+
+                    if ui.button("Rooms").clicked() {
+                        self.rooms_open = true;
+                        let _ = self.tabs[self.current_tab].send(ClientRequest::ListRooms);
                     }
 
                     if ui.button("Reconnect").clicked() {
-                        self.tabs[self.current_tab].reconnect(ctx.clone());
+                        self.tabs[self.current_tab].reconnect();
                     }
 
                     if ui
                         .add_enabled(self.tabs.len() != 1, egui::Button::new("Close"))
                         .clicked()
                     {
+                        // Later panels this frame show the current tab
                         self.tabs.remove(self.current_tab);
+                        self.current_tab = self.current_tab.min(self.tabs.len() - 1);
                     }
                 });
             });
         });
 
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            let tab = &self.tabs[self.current_tab];
+
+            ui.horizontal(|ui| {
+                ui.label(tab.connection.server());
+                ui.separator();
+
+                ui.label(match tab.connect_state {
+                    ConnectState::Loading => "Connecting",
+                    ConnectState::Connected => "Connected",
+                    ConnectState::Disconnect => "Disconnected",
+                    ConnectState::Failed => "Couldn't connect",
+                    ConnectState::Reconnecting { .. } => "Reconnecting",
+                });
+
+                if let Some(latency) = tab.latency {
+                    ui.separator();
+                    ui.label(format!("Latency {} ms", latency.as_millis()));
+                }
+
+                if let Some(notice) = &tab.notice {
+                    ui.separator();
+                    ui.colored_label(egui::Color32::YELLOW, notice);
+                }
+            });
+        });
+
         egui::SidePanel::left("left_panel").show(ctx, |ui| {
             let mut to_remove = Vec::new();
+            let mut to_reconnect = Vec::new();
+            let mut to_stop = Vec::new();
+            let mut to_select = None;
+            let mut to_leave = Vec::new();
+            let mut to_close = Vec::new();
             for (i, tab) in self.tabs.iter().enumerate() {
                 ui.horizontal(|ui| {
                     let text = match tab.connect_state {
@@ -213,11 +767,35 @@ impl eframe::App for Client {
                         ConnectState::Disconnect | ConnectState::Failed => {
                             egui::RichText::new(tab.connection.server()).color(egui::Color32::RED)
                         }
+                        ConnectState::Reconnecting { .. } => {
+                            ui.spinner();
+                            egui::RichText::new(tab.connection.server())
+                                .color(egui::Color32::YELLOW)
+                        }
                         _ => egui::RichText::new(tab.connection.server()),
                     };
 
-                    if ui.button(text).clicked() {
-                        self.current_tab = i;
+                    let mut response = ui.button(text);
+                    if let Some(error) = &tab.error {
+                        response = response.on_hover_text(error.to_string());
+                    }
+
+                    if response.clicked() {
+                        to_select = Some((i, View::Server));
+                    }
+
+                    let (unread, mentions) = tab.unread();
+                    if mentions > 0 {
+                        ui.label(
+                            egui::RichText::new(format!("@{}", mentions))
+                                .strong()
+                                .color(egui::Color32::LIGHT_RED),
+                        )
+                        .on_hover_text("Mentions of you");
+                    }
+                    if unread > 0 {
+                        ui.label(egui::RichText::new(unread.to_string()).strong())
+                            .on_hover_text("Unread messages");
                     }
 
                     if ui
@@ -230,6 +808,83 @@ impl eframe::App for Client {
                         to_remove.push(i);
                     }
                 });
+
+                if let ConnectState::Reconnecting { attempt, next_in } = tab.connect_state {
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "Attempt {} in {}s",
+                            attempt,
+                            next_in.as_secs_f32().ceil()
+                        ));
+
+                        if ui.small_button("Reconnect now").clicked() {
+                            to_reconnect.push(i);
+                        }
+
+                        if ui.small_button("Stop").clicked() {
+                            to_stop.push(i);
+                        }
+                    });
+                }
+
+                for (name, room) in &tab.rooms {
+                    ui.horizontal(|ui| {
+                        ui.add_space(16.0);
+
+                        let selected = i == self.current_tab
+                            && matches!(&tab.view, View::Room(room) if room == name);
+                        if ui
+                            .selectable_label(
+                                selected,
+                                chat_label(&format!("#{}", name), &room.chat),
+                            )
+                            .clicked()
+                        {
+                            to_select = Some((i, View::Room(name.clone())));
+                        }
+
+                        if ui.small_button("❌").on_hover_text("Leave").clicked() {
+                            to_leave.push((i, name.clone()));
+                        }
+                    });
+                }
+
+                for (j, conversation) in tab.conversations.iter().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add_space(16.0);
+
+                        let selected = i == self.current_tab && tab.view == View::Conversation(j);
+                        if ui
+                            .selectable_label(
+                                selected,
+                                chat_label(&conversation.peer, &conversation.chat),
+                            )
+                            .clicked()
+                        {
+                            to_select = Some((i, View::Conversation(j)));
+                        }
+
+                        if ui.small_button("❌").clicked() {
+                            to_close.push((i, j));
+                        }
+                    });
+                }
+            }
+            if let Some((i, view)) = to_select {
+                self.current_tab = i;
+                self.tabs[i].view = view;
+            }
+            for (i, name) in to_leave {
+                self.tabs[i].leave_room(&name);
+            }
+            for (i, j) in to_close.into_iter().rev() {
+                self.tabs[i].close_conversation(j);
+            }
+            for i in to_reconnect {
+                self.tabs[i].reconnect();
+            }
+            for i in to_stop {
+                self.tabs[i].stop_reconnecting();
             }
             for i in to_remove {
                 self.tabs.remove(i);
@@ -247,108 +902,257 @@ impl eframe::App for Client {
             }
         });
 
+        if self.search.open {
+            self.search_panel(ctx);
+        }
+
+        if self.show_roster {
+            self.roster_panel(ctx);
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            let tab = &mut self.tabs[self.current_tab];
+
+            if let Some(error) = &tab.error {
+                ui.colored_label(egui::Color32::RED, error.to_string());
+            }
+
+            let shown = Some((self.current_tab, tab.view.clone()));
+            let returned = self.last_shown != shown;
+            self.last_shown = shown;
+
+            tab.current_chat().mark_read(returned);
+            if returned {
+                tab.completion = None;
+            }
+            let pending = tab.pending();
+
+            let selected = match &self.search.selected {
+                Some((i, view, row)) if *i == self.current_tab && *view == tab.view => Some(*row),
+                _ => None,
+            };
+            let chat = match &tab.view {
+                View::Server => &mut tab.chat,
+                View::Room(name) => {
+                    ui.strong(format!("#{}", name));
+
+                    &mut tab.rooms.get_mut(name).unwrap().chat
+                }
+                View::Conversation(i) => {
+                    let peer = &tab.conversations[*i].peer;
+
+                    ui.horizontal(|ui| {
+                        ui.strong(format!("Direct messages with {}", peer));
+                        if tab.connect_state == ConnectState::Connected
+                            && !tab.roster.contains(peer)
+                        {
+                            ui.weak("(offline)");
+                        }
+                    });
+
+                    &mut tab.conversations[*i].chat
+                }
+            };
+
+            let view = self.chat_views.entry(chat.name().to_string()).or_default();
+            if selected.is_some() && self.search.scroll_to_selected {
+                view.scroll_to_row = selected;
+                self.search.scroll_to_selected = false;
+            }
+
+            let mut pending_action = None;
             ui.allocate_ui(
                 vec2(ui.available_width(), ui.available_height() - 20.0),
                 |ui| {
-                    egui::ScrollArea::vertical()
-                        .auto_shrink([false, false])
-                        .stick_to_bottom()
-                        .show(ui, |ui| {
-                            egui::Grid::new("message_grid")
-                                .num_columns(2)
-                                .show(ui, |ui| {
-                                    for row in 0..self.tabs[self.current_tab].messages.len() {
-                                        let c = &self.tabs[self.current_tab].messages[row];
-
-                                        for col in 0..2 {
-                                            if col == 0 {
-                                                ui.with_layout(
-                                                    egui::Layout::right_to_left(),
-                                                    |ui| match c {
-                                                        ChatCommands::Message {
-                                                            sender,
-                                                            message: _,
-                                                        } => {
-                                                            ui.heading(sender);
-                                                        }
-
-                                                        ChatCommands::UserConnected { name: _ } => {
-                                                            ui.heading(
-                                                                egui::RichText::new("+")
-                                                                    .color(egui::Color32::GREEN),
-                                                            );
-                                                        }
-
-                                                        ChatCommands::UserDisconnected {
-                                                            name: _,
-                                                        } => {
-                                                            ui.heading(
-                                                                egui::RichText::new("-")
-                                                                    .color(egui::Color32::RED),
-                                                            );
-                                                        }
-
-                                                        _ => {
-                                                            ui.heading(
-                                                                egui::RichText::new("!").color(
-                                                                    egui::Color32::DARK_GREEN,
-                                                                ),
-                                                            );
-                                                        }
-                                                    },
-                                                );
-                                            } else {
-                                                match c {
-                                                    ChatCommands::Message {
-                                                        sender: _,
-                                                        message,
-                                                    } => {
-                                                        ui.label(message);
-                                                    }
-
-                                                    ChatCommands::UserConnected { name } => {
-                                                        ui.strong(format!("{} connected", name));
-                                                    }
-
-                                                    ChatCommands::UserDisconnected { name } => {
-                                                        ui.strong(format!("{} disconnected", name));
-                                                    }
-
-                                                    ChatCommands::UserRenamed {
-                                                        oldname,
-                                                        newname,
-                                                    } => {
-                                                        ui.strong(format!(
-                                                            "{} changed names to {}",
-                                                            oldname, newname
-                                                        ));
-                                                    }
-                                                }
-                                            }
-                                        }
-                                        ui.end_row();
-                                    }
-                                });
-                        });
+                    pending_action = message_view(
+                        ui,
+                        chat,
+                        view,
+                        &pending,
+                        tab.connection.name(),
+                        self.time_format,
+                        selected,
+                    );
                 },
             );
 
-            let response = ui.add(
-                egui::TextEdit::singleline(&mut self.tabs[self.current_tab].message)
-                    .desired_width(f32::INFINITY)
-                    .hint_text("Enter message..."),
-            );
+            match pending_action {
+                Some(PendingAction::Edit(i)) => {
+                    tab.edit_pending(i);
+                    self.focus_composer = true;
+                }
+                Some(PendingAction::Cancel(i)) => tab.cancel_pending(i),
+                None => (),
+            }
+
+            let hint = match &tab.view {
+                View::Server => "Enter message...".to_string(),
+                View::Room(name) => format!("Message #{}...", name),
+                View::Conversation(i) => format!("Message {}...", tab.conversations[*i].peer),
+            };
+            let composer_id = ui.id().with("composer");
+
+            if ui.memory().has_focus(composer_id)
+                && ui
+                    .input_mut()
+                    .consume_key(egui::Modifiers::NONE, egui::Key::Tab)
+            {
+                let cursor = egui::text_edit::TextEditState::load(ui.ctx(), composer_id)
+                    .and_then(|state| state.ccursor_range())
+                    .map_or(tab.current_chat().message.chars().count(), |range| {
+                        range.primary.index
+                    });
+
+                if let Some(cursor) = tab.complete(cursor) {
+                    set_cursor(ui.ctx(), composer_id, cursor);
+                }
+            }
+
+            let response = egui::TextEdit::singleline(&mut tab.current_chat().message)
+                .id(composer_id)
+                .desired_width(f32::INFINITY)
+                .hint_text(hint)
+                .lock_focus(true)
+                .show(ui)
+                .response;
+
+            if response.changed() {
+                tab.command_error = None;
+            }
 
             if response.lost_focus() && ui.input().key_pressed(egui::Key::Enter) {
-                self.tabs[self.current_tab].send_message();
+                let command = tab.send_message();
+                self.run(command);
+                response.request_focus();
+            }
+
+            // Above the composer, whatever it's typing could be
+            let tab = &mut self.tabs[self.current_tab];
+            let commands = slash::matching(&tab.current_chat().message);
+            let message = tab.current_chat().message.clone();
+            let names = tab
+                .completion
+                .as_ref()
+                .filter(|completion| completion.is_current(&message))
+                .filter(|completion| completion.candidates().len() > 1)
+                .map(|completion| (completion.candidates().to_vec(), completion.selected()));
+
+            if !commands.is_empty() || names.is_some() || tab.command_error.is_some() {
+                let offset = response.rect.left_top() - ctx.available_rect().left_bottom();
+
+                egui::Area::new("composer_popup")
+                    .order(egui::Order::Foreground)
+                    .anchor(egui::Align2::LEFT_BOTTOM, offset)
+                    .show(ctx, |ui| {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            if let Some(error) = &tab.command_error {
+                                ui.colored_label(egui::Color32::RED, error);
+                            }
+
+                            for spec in commands {
+                                let label = ui
+                                    .selectable_label(false, spec.usage())
+                                    .on_hover_text(spec.about);
+                                if label.clicked() {
+                                    let message = &mut tab.current_chat().message;
+                                    *message = format!("/{} ", spec.name);
+                                    set_cursor(ctx, composer_id, message.chars().count());
+                                    self.focus_composer = true;
+                                }
+                            }
+
+                            if let Some((names, selected)) = names {
+                                for (i, name) in names.iter().enumerate() {
+                                    if ui.selectable_label(selected == Some(i), name).clicked() {
+                                        if let Some(cursor) = tab.select_completion(i) {
+                                            set_cursor(ctx, composer_id, cursor);
+                                            self.focus_composer = true;
+                                        }
+                                    }
+                                }
+                            }
+                        });
+                    });
+            }
+
+            if self.focus_composer {
                 response.request_focus();
+                self.focus_composer = false;
             }
         });
 
+        let tab = &mut self.tabs[self.current_tab];
+        match tab.error.clone() {
+            Some(NetError::UntrustedCertificate(fingerprint)) => {
+                egui::Window::new("Untrusted certificate")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.label(format!(
+                            "{} presented a certificate that hasn't been trusted yet. \
+                             Only trust it if the fingerprint matches the one given by the server's owner.",
+                            tab.connection.server()
+                        ));
+                        ui.monospace(&fingerprint);
+
+                        ui.with_layout(egui::Layout::right_to_left(), |ui| {
+                            if ui.button("Trust").clicked() {
+                                tab.connection.set_fingerprint(Some(fingerprint.clone()));
+                                tab.reconnect();
+                            }
+
+                            if ui.button("Cancel").clicked() {
+                                tab.error = None;
+                            }
+                        });
+                    });
+            }
+
+            Some(NetError::CertificateChanged { pinned, presented }) => {
+                egui::Window::new("Certificate changed")
+                    .collapsible(false)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        ui.colored_label(
+                            egui::Color32::RED,
+                            format!(
+                                "The certificate presented by {} doesn't match the one trusted before. \
+                                 Someone may be intercepting the connection, so it has been refused.",
+                                tab.connection.server()
+                            ),
+                        );
+                        ui.label("Trusted:");
+                        ui.monospace(&pinned);
+                        ui.label("Presented:");
+                        ui.monospace(&presented);
+                        ui.label(
+                            "If the server's owner has replaced their certificate, \
+                             forget the trusted one under Server > Edit.",
+                        );
+
+                        ui.with_layout(egui::Layout::right_to_left(), |ui| {
+                            if ui.button("Close").clicked() {
+                                tab.error = None;
+                            }
+                        });
+                    });
+            }
+
+            _ => (),
+        }
+
+        if self.rooms_open {
+            self.rooms_window(ctx);
+        }
+
+        if self.help.is_some() {
+            self.help_window(ctx);
+        }
+
         if self.server_edit != ServerEdit::None {
             egui::Window::new("Server details")
-                .fixed_size((200.0, 60.0))
+                .default_width(200.0)
+                .resizable(false)
                 .show(ctx, |ui| {
                     ui.horizontal(|ui| {
                         ui.label("Server address");
@@ -368,47 +1172,448 @@ impl eframe::App for Client {
                         );
                     });
 
+                    ui.checkbox(&mut self.server_edit_tls, "Use TLS");
+                    if let Some(fingerprint) = self.server_edit_fingerprint.clone() {
+                        ui.horizontal(|ui| {
+                            ui.label("Trusted certificate");
+                            if ui.small_button("Forget").clicked() {
+                                self.server_edit_fingerprint = None;
+                                self.server_edit_forget_fingerprint = true;
+                            }
+                        });
+                        ui.monospace(fingerprint);
+                    }
+
+                    let reconnect = &mut self.server_edit_reconnect;
+                    ui.checkbox(&mut reconnect.enabled, "Reconnect automatically");
+                    ui.add_enabled_ui(reconnect.enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Delay from");
+                            duration_drag(ui, &mut reconnect.initial_delay);
+                            ui.label("up to");
+                            duration_drag(ui, &mut reconnect.max_delay);
+                        });
+
+                        ui.horizontal(|ui| {
+                            let mut limited = reconnect.max_attempts.is_some();
+                            let mut attempts = reconnect.max_attempts.unwrap_or(10);
+
+                            ui.checkbox(&mut limited, "Give up after");
+                            ui.add_enabled(
+                                limited,
+                                egui::DragValue::new(&mut attempts).clamp_range(1..=1000),
+                            );
+                            ui.label("attempts");
+
+                            reconnect.max_attempts = limited.then_some(attempts);
+                        });
+                    });
+
+                    let keepalive = &mut self.server_edit_keepalive;
+                    ui.checkbox(&mut keepalive.enabled, "Check the connection is alive");
+                    ui.add_enabled_ui(keepalive.enabled, |ui| {
+                        ui.horizontal(|ui| {
+                            ui.label("Ping every");
+                            duration_drag(ui, &mut keepalive.interval);
+                            ui.label("and drop after");
+                            duration_drag(ui, &mut keepalive.timeout);
+                            ui.label("of silence");
+                        });
+                    });
+
+                    let retention = &mut self.server_edit_retention;
+                    ui.horizontal(|ui| {
+                        let mut limited = retention.days.is_some();
+                        let mut days = retention.days.unwrap_or(30);
+
+                        ui.checkbox(&mut limited, "Keep history for");
+                        ui.add_enabled(
+                            limited,
+                            egui::DragValue::new(&mut days).clamp_range(1..=3650),
+                        );
+                        ui.label("days");
+
+                        retention.days = limited.then_some(days);
+                    });
+                    ui.horizontal(|ui| {
+                        let mut limited = retention.messages.is_some();
+                        let mut messages = retention.messages.unwrap_or(10_000);
+
+                        ui.checkbox(&mut limited, "Keep at most");
+                        ui.add_enabled(
+                            limited,
+                            egui::DragValue::new(&mut messages)
+                                .clamp_range(1..=1_000_000)
+                                .speed(10),
+                        );
+                        ui.label("messages");
+
+                        retention.messages = limited.then_some(messages);
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Hold");
+                        ui.add(
+                            egui::DragValue::new(&mut retention.in_memory)
+                                .clamp_range(100..=1_000_000)
+                                .speed(10),
+                        );
+                        ui.label("messages per chat in memory");
+                    });
+
                     ui.with_layout(egui::Layout::right_to_left(), |ui| {
                         if ui.button("Add").clicked() {
-                            match self.server_edit {
-                                ServerEdit::New => self.tabs.push(Tab::new(
-                                    ctx.clone(),
-                                    ConnectionData::new(
-                                        &self.server_edit_address,
-                                        &self.server_edit_name,
-                                    ),
-                                )),
-
-                                ServerEdit::Change(i) => {
-                                    if self.server_edit_address != *self.tabs[i].connection.server()
-                                    {
-                                        self.tabs[i] = Tab::new(
-                                            ctx.clone(),
-                                            ConnectionData::new(
-                                                &self.server_edit_address,
-                                                &self.server_edit_name,
-                                            ),
-                                        );
-                                    } else {
-                                        self.tabs[i].change_name(&self.server_edit_name);
-                                    }
+                            let (address, name) =
+                                (&self.server_edit_address, &self.server_edit_name);
+                            let reconnect = self.server_edit_reconnect.clone();
+                            let keepalive = self.server_edit_keepalive.clone();
+                            let tls = self.server_edit_tls;
+                            let retention = self.server_edit_retention.clone();
+                            let forget_fingerprint = self.server_edit_forget_fingerprint;
+
+                            let edit = |connection: &mut ConnectionData| {
+                                connection.set_server(address);
+                                connection.set_name(name);
+                                connection.set_reconnect(reconnect);
+                                connection.set_keepalive(keepalive);
+                                connection.set_tls(tls);
+                                connection.set_retention(retention);
+
+                                // The form's copy is stale if one was trusted while it was open
+                                if forget_fingerprint {
+                                    connection.set_fingerprint(None);
                                 }
+                            };
 
+                            match self.server_edit {
+                                ServerEdit::New => {
+                                    let mut connection = ConnectionData::default();
+                                    edit(&mut connection);
+                                    self.tabs.push(Session::new_with(
+                                        self.persistence.clone(),
+                                        connection,
+                                        waker(ctx),
+                                    ));
+                                }
+                                ServerEdit::Change(i) => self.tabs[i].edit_connection(edit),
                                 _ => unreachable!(),
                             }
 
-                            self.server_edit_address.clear();
-                            self.server_edit_name.clear();
-                            self.server_edit = ServerEdit::None;
+                            self.close_server_edit();
                         }
 
                         if ui.button("Cancel").clicked() {
-                            self.server_edit_address.clear();
-                            self.server_edit_name.clear();
-                            self.server_edit = ServerEdit::None;
+                            self.close_server_edit();
                         }
                     });
                 });
         }
     }
 }
+
+/// Puts the cursor of the text edit `id` this many characters in, after
+/// changing its text.
+fn set_cursor(ctx: &egui::Context, id: egui::Id, chars: usize) {
+    let mut state = egui::text_edit::TextEditState::load(ctx, id).unwrap_or_default();
+    let cursor = egui::text::CCursor::new(chars);
+    state.set_ccursor_range(Some(egui::text::CCursorRange::one(cursor)));
+    state.store(ctx, id);
+}
+
+/// A chat's name in the server list, marked if there's anything new in it.
+fn chat_label(name: &str, chat: &Chat) -> egui::RichText {
+    if chat.mentions() > 0 {
+        egui::RichText::new(format!("{} ({})", name, chat.unread()))
+            .strong()
+            .color(egui::Color32::LIGHT_RED)
+    } else if chat.unread() > 0 {
+        egui::RichText::new(format!("{} ({})", name, chat.unread())).strong()
+    } else {
+        egui::RichText::new(name)
+    }
+}
+
+/// What to do with a message waiting in the outbox.
+enum PendingAction {
+    Edit(usize),
+    Cancel(usize),
+}
+
+/// Width of the column of senders in the message view.
+const SENDER_WIDTH: f32 = 120.0;
+
+/// Lays out `chat`'s messages with their senders and times, highlighting the
+/// `selected` row, followed by our `pending` messages.
+///
+/// Only the rows in view are laid out, and older messages are read back from
+/// history when scrolled to.
+fn message_view(
+    ui: &mut egui::Ui,
+    chat: &mut Chat,
+    view: &mut ChatView,
+    pending: &[(usize, ClientRequest)],
+    name: &str,
+    time_format: TimeFormat,
+    selected: Option<usize>,
+) -> Option<PendingAction> {
+    let now = SystemTime::now();
+    let mut action = None;
+
+    if let Some(row) = view.scroll_to_row.filter(|&row| row < chat.paged_out()) {
+        chat.read_back(chat.paged_out() - row);
+    }
+    view.follow(chat);
+
+    let selected = selected.and_then(|row| row.checked_sub(chat.paged_out()));
+    let scroll_to_row = view
+        .scroll_to_row
+        .take()
+        .and_then(|row| row.checked_sub(chat.paged_out()));
+    let divider = chat
+        .divider()
+        .and_then(|row| row.checked_sub(chat.paged_out()));
+
+    let time_width = ui
+        .fonts()
+        .layout_no_wrap(
+            time_format.format(now, now),
+            egui::TextStyle::Small.resolve(ui.style()),
+            egui::Color32::TEMPORARY_COLOR,
+        )
+        .size()
+        .x;
+    let estimate = ui.text_style_height(&egui::TextStyle::Heading) + ui.spacing().item_spacing.y;
+
+    let mut scroll_area = egui::ScrollArea::vertical()
+        .auto_shrink([false, false])
+        .stick_to_bottom();
+    if let Some(offset) = view.scroll_to.take() {
+        scroll_area = scroll_area.vertical_scroll_offset(offset);
+    }
+
+    let output = scroll_area.show_viewport(ui, |ui, viewport| {
+        let messages = chat.messages();
+        let heights = &mut view.heights;
+
+        heights.resize(
+            messages.len() + pending.len(),
+            ui.available_width(),
+            estimate,
+        );
+        ui.set_height(heights.total());
+
+        let top = ui.max_rect().top();
+        if let Some(row) = scroll_to_row {
+            let rect = egui::Rect::from_x_y_ranges(
+                ui.max_rect().x_range(),
+                top + heights.top(row)..=top + heights.top(row + 1),
+            );
+            ui.scroll_to_rect(rect, Some(egui::Align::Center));
+        }
+
+        let rows = heights.visible(viewport.min.y, viewport.max.y);
+        let rect = egui::Rect::from_x_y_ranges(
+            ui.max_rect().x_range(),
+            top + heights.top(rows.start)..=top + heights.top(rows.end),
+        );
+
+        let mut resized = false;
+        ui.allocate_ui_at_rect(rect, |ui| {
+            ui.skip_ahead_auto_ids(rows.start);
+
+            for row in rows {
+                let row_top = ui.cursor().top();
+
+                if let Some(record) = messages.get(row) {
+                    // Separate each day's messages
+                    if row == 0 || !timestamp::same_day(messages[row - 1].when(), record.when()) {
+                        ui.horizontal(|ui| {
+                            ui.add_space(SENDER_WIDTH);
+                            ui.label(
+                                egui::RichText::new(timestamp::day_label(record.when())).weak(),
+                            );
+                        });
+                    }
+
+                    // Mark where we stopped reading
+                    if divider == Some(row) {
+                        ui.horizontal(|ui| {
+                            ui.add_space(SENDER_WIDTH);
+                            let label = ui.label(
+                                egui::RichText::new("New messages")
+                                    .small()
+                                    .color(egui::Color32::LIGHT_RED),
+                            );
+                            ui.painter().hline(
+                                label.rect.right() + ui.spacing().item_spacing.x
+                                    ..=ui.max_rect().right(),
+                                label.rect.center().y,
+                                (1.0, egui::Color32::LIGHT_RED),
+                            );
+                        });
+                    }
+
+                    let c = &record.command;
+                    let sender = match c {
+                        ChatCommands::Message { message, .. }
+                        | ChatCommands::PrivateMessage { message, .. }
+                        | ChatCommands::RoomMessage { message, .. }
+                            if slash::action(message).is_some() =>
+                        {
+                            egui::RichText::new("*").heading()
+                        }
+                        ChatCommands::Message { sender, .. }
+                        | ChatCommands::PrivateMessage { sender, .. }
+                        | ChatCommands::RoomMessage { sender, .. } => {
+                            egui::RichText::new(sender).heading()
+                        }
+
+                        ChatCommands::UserConnected { .. } | ChatCommands::UserJoined { .. } => {
+                            egui::RichText::new("+")
+                                .heading()
+                                .color(egui::Color32::GREEN)
+                        }
+
+                        ChatCommands::UserDisconnected { .. } | ChatCommands::UserLeft { .. } => {
+                            egui::RichText::new("-").heading().color(egui::Color32::RED)
+                        }
+
+                        _ => egui::RichText::new("!")
+                            .heading()
+                            .color(egui::Color32::DARK_GREEN),
+                    };
+
+                    let mut text = match c {
+                        ChatCommands::Message { sender, message }
+                        | ChatCommands::PrivateMessage { sender, message }
+                        | ChatCommands::RoomMessage {
+                            sender, message, ..
+                        } => match slash::action(message) {
+                            Some(action) => {
+                                egui::RichText::new(format!("{} {}", sender, action)).italics()
+                            }
+                            None => egui::RichText::new(message),
+                        },
+                        _ => egui::RichText::new(c.text()).strong(),
+                    };
+                    if selected == Some(row) {
+                        text = text.background_color(ui.visuals().selection.bg_fill);
+                    }
+
+                    message_columns(ui, sender, text, time_width, |ui| {
+                        let mut details =
+                            format!("Received {}", TimeFormat::Full.format(record.time, now));
+                        if let Some(server_time) = record.server_time {
+                            details +=
+                                &format!("\nSent {}", TimeFormat::Full.format(server_time, now));
+                        }
+
+                        ui.label(
+                            egui::RichText::new(time_format.format(record.when(), now))
+                                .small()
+                                .weak(),
+                        )
+                        .on_hover_text(details);
+                    });
+                } else {
+                    let (i, request) = &pending[row - messages.len()];
+
+                    message_columns(
+                        ui,
+                        egui::RichText::new(name).heading().weak(),
+                        egui::RichText::new(request_text(request)).weak().italics(),
+                        time_width,
+                        |ui| {
+                            ui.label(egui::RichText::new("pending").small().weak())
+                                .on_hover_text("Sent once connected");
+
+                            if ui.small_button("Edit").clicked() {
+                                action = Some(PendingAction::Edit(*i));
+                            }
+                            if ui.small_button("Cancel").clicked() {
+                                action = Some(PendingAction::Cancel(*i));
+                            }
+                        },
+                    );
+                }
+
+                resized |= heights.set(row, ui.cursor().top() - row_top);
+            }
+        });
+
+        // Lay out again with the heights just measured
+        if resized {
+            ui.ctx().request_repaint();
+        }
+    });
+
+    // Read back older messages when scrolled to the top, keeping the same
+    // messages in view, and page them out again once back at the bottom
+    let offset = output.state.offset.y;
+    let shown = output.inner_rect.height();
+    let total = view.heights.total();
+    if total > shown {
+        if offset <= 0.0 {
+            let read = chat.read_back(READ_BACK);
+            if read > 0 {
+                view.follow(chat);
+                view.scroll_to = Some(view.heights.top(read));
+                ui.ctx().request_repaint();
+            }
+        } else if offset + shown >= total {
+            chat.forget_read_back();
+        }
+    }
+
+    action
+}
+
+/// Lays out a row of the message view: the `sender`, then the `text`
+/// wrapped to fit, then whatever `end` adds in the last `end_width`.
+fn message_columns(
+    ui: &mut egui::Ui,
+    sender: egui::RichText,
+    text: egui::RichText,
+    end_width: f32,
+    end: impl FnOnce(&mut egui::Ui),
+) {
+    let spacing = ui.spacing().item_spacing.x;
+    let text_width = (ui.available_width() - SENDER_WIDTH - end_width - 2.0 * spacing).max(100.0);
+
+    ui.horizontal_top(|ui| {
+        ui.allocate_ui_with_layout(
+            vec2(SENDER_WIDTH, 0.0),
+            egui::Layout::top_down(egui::Align::Max),
+            |ui| {
+                ui.set_width(SENDER_WIDTH);
+                ui.add(egui::Label::new(sender).wrap(true));
+            },
+        );
+        ui.allocate_ui_with_layout(
+            vec2(text_width, 0.0),
+            egui::Layout::top_down(egui::Align::Min),
+            |ui| {
+                ui.set_width(text_width);
+                ui.add(egui::Label::new(text).wrap(true));
+            },
+        );
+
+        end(ui);
+    });
+}
+
+/// Edits a duration as a number of seconds.
+fn duration_drag(ui: &mut egui::Ui, duration: &mut Duration) {
+    let mut secs = duration.as_secs_f32();
+
+    if ui
+        .add(
+            egui::DragValue::new(&mut secs)
+                .clamp_range(0.1..=3600.0)
+                .speed(0.1)
+                .suffix("s"),
+        )
+        .changed()
+    {
+        *duration = Duration::from_secs_f32(secs);
+    }
+}
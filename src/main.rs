@@ -2,19 +2,82 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
 mod app;
-mod net;
+mod scrollback;
 
-use app::Client;
+use app::{Client, Options};
+use chat_core::{
+    net::{connection::ConnectionData, uri::UriError},
+    session::Persistence,
+};
+
+use clap::Parser;
+use std::path::PathBuf;
+
+/// A client for the chatting protocol.
+#[derive(Parser)]
+#[command(version, about)]
+struct Args {
+    /// Server to open, as a link like `chat://name@host:port` or just
+    /// `host:port`.
+    #[arg(value_parser = server)]
+    server: Option<ConnectionData>,
+
+    /// Name to use on that server.
+    #[arg(long, requires = "server")]
+    name: Option<String>,
+
+    /// Keep settings, history and unsent messages in this directory.
+    #[arg(long, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+
+    /// Don't load or save settings, history or unsent messages.
+    #[arg(long, conflicts_with = "config_dir")]
+    no_persist: bool,
+
+    /// Log messages this important and up, from `error` to `trace`.
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<tracing::Level>,
+}
+
+/// Reads a server given as a link, or as much of one as comes after `chat://`.
+fn server(arg: &str) -> Result<ConnectionData, UriError> {
+    if arg.contains("://") {
+        arg.parse()
+    } else {
+        format!("chat://{}", arg).parse()
+    }
+}
 
 #[tokio::main]
 async fn main() {
-    // Log to stdout (if you run with `RUST_LOG=debug`).
-    tracing_subscriber::fmt::init();
+    let args = Args::parse();
+
+    // Log to stdout (if you run with `RUST_LOG=debug` or `--log-level debug`).
+    match args.log_level {
+        Some(level) => tracing_subscriber::fmt().with_max_level(level).init(),
+        None => tracing_subscriber::fmt::init(),
+    }
+
+    let mut server = args.server;
+    if let (Some(server), Some(name)) = (&mut server, &args.name) {
+        server.set_name(name);
+    }
+
+    let persistence = match args.config_dir {
+        _ if args.no_persist => Persistence::Off,
+        Some(dir) => Persistence::In(dir),
+        None => Persistence::Default,
+    };
+
+    let options = Options {
+        server,
+        persistence,
+    };
 
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
-        "Chatting client",
+        chat_core::APP_NAME,
         native_options,
-        Box::new(|cc| Box::new(Client::new(cc, None))),
+        Box::new(|cc| Box::new(Client::new(cc, options))),
     );
 }
@@ -1,3 +0,0 @@
-pub mod client;
-pub mod commands;
-pub mod connection;
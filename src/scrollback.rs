@@ -0,0 +1,159 @@
+use std::ops::Range;
+
+/// Heights of a long list's rows as last drawn, so only the rows in view
+/// need laying out each frame.
+///
+/// Rows that haven't been drawn yet are assumed to be `estimate` high, and
+/// every height is forgotten when the width changes, as that changes how
+/// text wraps.
+#[derive(Default, Debug)]
+pub struct RowHeights {
+    width: f32,
+    estimate: f32,
+    heights: Vec<f32>,
+    /// Top of each row, then the bottom of the last, correct as far as the
+    /// top of row `stale_from`.
+    offsets: Vec<f32>,
+    stale_from: usize,
+}
+
+impl RowHeights {
+    /// Prepares for drawing `rows` rows at `width`, keeping what is known
+    /// about rows that are still there.
+    pub fn resize(&mut self, rows: usize, width: f32, estimate: f32) {
+        if width != self.width || estimate != self.estimate {
+            self.width = width;
+            self.estimate = estimate;
+            self.heights.clear();
+            self.stale_from = 0;
+        }
+
+        if rows < self.heights.len() {
+            self.heights.truncate(rows);
+        } else {
+            self.heights.resize(rows, estimate);
+        }
+        self.stale_from = self.stale_from.min(rows);
+    }
+
+    /// Records the height of `row` as drawn, returning whether it changed.
+    pub fn set(&mut self, row: usize, height: f32) -> bool {
+        match self.heights.get_mut(row) {
+            Some(known) if *known != height => {
+                *known = height;
+                self.stale_from = self.stale_from.min(row);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Adds `rows` rows of unknown height before the first.
+    pub fn insert_front(&mut self, rows: usize) {
+        self.heights
+            .splice(0..0, std::iter::repeat_n(self.estimate, rows));
+        self.stale_from = 0;
+    }
+
+    /// Forgets the first `rows` rows.
+    pub fn remove_front(&mut self, rows: usize) {
+        self.heights.drain(..rows.min(self.heights.len()));
+        self.stale_from = 0;
+    }
+
+    fn offsets(&mut self) -> &[f32] {
+        let rows = self.heights.len();
+        if self.stale_from < rows || self.offsets.len() != rows + 1 {
+            self.offsets.truncate(self.stale_from + 1);
+            if self.offsets.is_empty() {
+                self.offsets.push(0.0);
+            }
+
+            let mut bottom = self.offsets[self.offsets.len() - 1];
+            for height in &self.heights[self.offsets.len() - 1..] {
+                bottom += height;
+                self.offsets.push(bottom);
+            }
+            self.stale_from = rows;
+        }
+
+        &self.offsets
+    }
+
+    /// Where `row` starts, or the bottom of the list past the last row.
+    pub fn top(&mut self, row: usize) -> f32 {
+        let offsets = self.offsets();
+        offsets[row.min(offsets.len() - 1)]
+    }
+
+    /// Height of the whole list.
+    pub fn total(&mut self) -> f32 {
+        let offsets = self.offsets();
+        offsets[offsets.len() - 1]
+    }
+
+    /// The rows that are at least partly between `top` and `bottom`.
+    pub fn visible(&mut self, top: f32, bottom: f32) -> Range<usize> {
+        let offsets = self.offsets();
+        let rows = offsets.len() - 1;
+
+        // The first row whose bottom is below the top, up to the first whose
+        // top is below the bottom
+        let first = offsets[1..].partition_point(|&end| end <= top);
+        let last = offsets[..rows].partition_point(|&start| start < bottom);
+
+        first..last.max(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unknown_rows_use_the_estimate() {
+        let mut heights = RowHeights::default();
+        heights.resize(100, 400.0, 20.0);
+
+        assert_eq!(heights.total(), 2000.0);
+        assert_eq!(heights.top(10), 200.0);
+        assert_eq!(heights.visible(210.0, 250.0), 10..13);
+        assert_eq!(heights.visible(0.0, 1.0), 0..1);
+        assert_eq!(heights.visible(2000.0, 2100.0), 100..100);
+    }
+
+    #[test]
+    fn measured_rows_move_the_ones_after() {
+        let mut heights = RowHeights::default();
+        heights.resize(10, 400.0, 20.0);
+        assert!(heights.set(2, 60.0));
+        assert!(!heights.set(2, 60.0));
+
+        assert_eq!(heights.top(2), 40.0);
+        assert_eq!(heights.top(3), 100.0);
+        assert_eq!(heights.total(), 240.0);
+        assert_eq!(heights.visible(50.0, 110.0), 2..4);
+
+        // Growing keeps what was measured, a new width doesn't
+        heights.resize(12, 400.0, 20.0);
+        assert_eq!(heights.total(), 280.0);
+        heights.resize(12, 300.0, 20.0);
+        assert_eq!(heights.total(), 240.0);
+    }
+
+    #[test]
+    fn rows_can_come_and_go_at_the_front() {
+        let mut heights = RowHeights::default();
+        heights.resize(3, 400.0, 20.0);
+        heights.set(0, 30.0);
+        heights.set(2, 50.0);
+
+        heights.remove_front(1);
+        assert_eq!(heights.total(), 70.0);
+        assert_eq!(heights.top(1), 20.0);
+
+        heights.insert_front(2);
+        assert_eq!(heights.total(), 110.0);
+        assert_eq!(heights.top(3), 60.0);
+    }
+}